@@ -0,0 +1,122 @@
+// Copyright 2022 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `futils apply` command.
+
+use crate::{
+    parallel::{ordered_map, worker_count},
+    shell::{FileOpener, Options, STDIN_PATHNAME, ShellResult, file_records, parse_options},
+    util::{command_output, exit_with_result, help, print_output},
+};
+use std::{ffi::OsString, io, process::Output, str};
+
+pub const APPLY_HELP: &str = include_str!("apply.md");
+pub const APPLY_HELP_VERBOSE: &str = include_str!("apply_verbose.md");
+
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "CfhjPrSvwxZ";
+
+fn split(record: &[u8], options: &Options) -> Vec<Vec<u8>> {
+    options
+        .input_field_delimiter
+        .split(record)
+        .map(<[u8]>::to_vec)
+        .collect()
+}
+
+/// Runs each of the `-x` commands with the `fields` as its arguments. If there
+/// are no `-x` commands, runs the first field as a command with the rest of
+/// the fields as its arguments.
+fn run(fields: &[Vec<u8>], options: &Options) -> Vec<io::Result<Output>> {
+    let fields: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+    if options.match_commands.is_empty() {
+        let Some((command, arguments)) = fields.split_first() else {
+            return Vec::new();
+        };
+        vec![
+            str::from_utf8(command)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+                .and_then(|command| command_output(command, arguments)),
+        ]
+    } else {
+        options
+            .match_commands
+            .iter()
+            .map(|command| command_output(command, &fields))
+            .collect()
+    }
+}
+
+/// Iterates over `records` (each of which is a list of fields) and runs the
+/// command(s) on each one. Returns the number of commands that failed.
+fn apply(records: impl Iterator<Item = Vec<Vec<u8>>>, options: &Options) -> i32 {
+    let mut status = 0;
+    ordered_map(
+        records.filter(|fields| fields.iter().any(|f| !f.is_empty())),
+        worker_count(options),
+        |fields| {
+            let outputs = run(&fields, options);
+            (fields, outputs)
+        },
+        |(fields, outputs)| {
+            for output in outputs {
+                let run_status = match output {
+                    Ok(output) => print_output(&output, true),
+                    Err(error) => Err(error.into()),
+                };
+                match run_status {
+                    Ok(run_status) => {
+                        if run_status != 0 {
+                            status += 1;
+                        }
+                    }
+                    Err(error) => {
+                        let record = fields.join(b" ".as_slice());
+                        eprintln!("{}: {error}", String::from_utf8_lossy(&record));
+                        status += 1;
+                    }
+                }
+            }
+        },
+    );
+    status
+}
+
+/// Runs the `apply` command on `arguments`.
+pub fn apply_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
+    if options.help {
+        exit_with_result(help(
+            0,
+            APPLY_HELP,
+            true,
+            if options.verbose {
+                Some(APPLY_HELP_VERBOSE)
+            } else {
+                None
+            },
+        ));
+    }
+    let mut status = 0;
+    for file in FileOpener::new(arguments, &options).mapped() {
+        let pathname = file.pathname.unwrap_or(&STDIN_PATHNAME);
+        match file.read {
+            Ok(mut read) => {
+                // Records that `-C` or `-j` already split keep their fields.
+                let mut records = file_records(file.map.as_deref(), &mut read, pathname, &options);
+                status += apply(
+                    records
+                        .by_ref()
+                        .map(|r| r.fields.unwrap_or_else(|| split(&r.r, &options))),
+                    &options,
+                );
+                status += records.errors;
+            }
+            Err(error) => {
+                eprintln!("{pathname}: {error}");
+                status += 1;
+            }
+        }
+    }
+    Ok(status)
+}
@@ -4,37 +4,63 @@
 //! The `futils common` command.
 
 use crate::{
-    shell::{Options, ShellResult, parse_options},
-    util::{exit_with_result, help, icmp},
+    output::{StructuredWrite, StructuredWriter, record_fields},
+    shell::{Options, Record, ShellResult, file_records, open_pair, parse_options},
+    util::{StrOrBytes, exit_with_result, help, icmp},
 };
-use regex_splitter::RegexSplitter;
+use serde::Serialize;
+use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::fs::File;
-use std::io::{Write, stdin, stdout};
+use std::ffi::OsString;
+use std::io::{self, Write, stdout};
 use std::process::exit;
 
 pub const COMMON_HELP: &str = include_str!("common.md");
 pub const COMMON_HELP_VERBOSE: &str = include_str!("common_verbose.md");
 
-fn print(column: i8, field: &[u8], options: &Options) -> ShellResult {
-    let mut out = stdout();
-    match column {
-        1 => (),
-        2 => out.write_all(&options.output_field_delimiter)?,
-        3 => {
-            out.write_all(&options.output_field_delimiter)?;
-            out.write_all(&options.output_field_delimiter)?;
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "CFhJjoRrSvZ";
+
+/// A record, and the column it goes in: 1 if it is only in the first input, 2
+/// if it is only in the second, or 3 if it is in both.
+#[derive(Serialize)]
+struct Common<'a> {
+    column: u8,
+    r: StrOrBytes<'a>,
+    /// The fields of `r`, if the input format delimited them.
+    #[serde(skip)]
+    fields: Option<&'a [Vec<u8>]>,
+}
+
+impl<'a> Common<'a> {
+    fn new(column: u8, record: &'a Record<'_>) -> Self {
+        Self {
+            column,
+            r: StrOrBytes(&record.r),
+            fields: record.fields.as_deref(),
         }
-        _ => unreachable!(),
     }
-    out.write_all(field)?;
-    out.write_all(&options.output_record_delimiter)?;
-    Ok(0)
+}
+
+impl StructuredWrite for Common<'_> {
+    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> Result<(), io::Error> {
+        for _ in 1..self.column {
+            output.write_all(&options.output_field_delimiter)?;
+        }
+        output.write_all(self.r.0)?;
+        output.write_all(&options.output_record_delimiter)
+    }
+
+    fn to_fields(&self, options: &Options) -> Vec<Cow<'_, [u8]>> {
+        let mut fields = vec![Cow::Borrowed(b"".as_slice()); usize::from(self.column) - 1];
+        fields.extend(record_fields(self.r.0, self.fields, options));
+        fields
+    }
 }
 
 /// Runs the `common` command on `arguments`.
-pub fn common_main(arguments: &[String]) -> ShellResult {
-    let (options, arguments) = parse_options(arguments)?;
+pub fn common_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
     if options.help {
         exit_with_result(help(
             0,
@@ -54,58 +80,46 @@ pub fn common_main(arguments: &[String]) -> ShellResult {
         }
     }
 
-    let mut stdin = stdin();
-    let mut file1 = File::open(&arguments[0])?;
-    let mut file2: File;
-    let records1: RegexSplitter;
-    let records2: RegexSplitter;
-    let delimiter = &options.input_record_delimiter;
-    if arguments.len() == 2 {
-        file2 = File::open(&arguments[1])?;
-        records1 = RegexSplitter::new(&mut file1, delimiter);
-        records2 = RegexSplitter::new(&mut file2, delimiter);
-    } else {
-        records1 = RegexSplitter::new(&mut stdin, delimiter);
-        records2 = RegexSplitter::new(&mut file1, delimiter);
-    }
-
-    let mut records1 = records1.map_while(Result::ok);
-    let mut records2 = records2.map_while(Result::ok);
+    let [(pathname1, mut read1), (pathname2, mut read2)] = open_pair(arguments, &options)?;
+    let mut records1 = file_records(None, &mut read1, pathname1, &options);
+    let mut records2 = file_records(None, &mut read2, pathname2, &options);
 
+    let mut output = StructuredWriter::new(stdout(), &options);
     // Adapted from *Command-Line Rust* by Ken Youens-Clark, pp. 242 – 243.
     let mut record1 = records1.next();
     let mut record2 = records2.next();
     while record1.is_some() || record2.is_some() {
         match (&record1, &record2) {
             (Some(r1), Some(r2)) => match if options.case_sensitive {
-                r1.cmp(r2)
+                r1.r.cmp(&r2.r)
             } else {
-                icmp(r1, r2)
+                icmp(&r1.r, &r2.r)
             } {
                 Ordering::Equal => {
-                    print(3, r1, &options)?;
+                    output.write(&Common::new(3, r1))?;
                     record1 = records1.next();
                     record2 = records2.next();
                 }
                 Ordering::Less => {
-                    print(1, r1, &options)?;
+                    output.write(&Common::new(1, r1))?;
                     record1 = records1.next();
                 }
                 Ordering::Greater => {
-                    print(2, r2, &options)?;
+                    output.write(&Common::new(2, r2))?;
                     record2 = records2.next();
                 }
             },
             (Some(r1), None) => {
-                print(1, r1, &options)?;
+                output.write(&Common::new(1, r1))?;
                 record1 = records1.next();
             }
             (None, Some(r2)) => {
-                print(2, r2, &options)?;
+                output.write(&Common::new(2, r2))?;
                 record2 = records2.next();
             }
             _ => (),
         }
     }
-    Ok(0)
+    output.finish()?;
+    Ok(records1.errors + records2.errors)
 }
@@ -0,0 +1,149 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defaults for `Options` from the configuration file and from `FUTILS_*`
+//! environment variables.
+
+use crate::shell::{EmptyResult, OPTION_SPECS, Options, UsageError, set_option, spec_options};
+use std::{
+    env,
+    fs::read_to_string,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// The options whose defaults can be configured, by short name. (It makes no
+/// sense to configure e.g. `-h` or `-m`.)
+const CONFIGURABLE: &str = "FfRrSt";
+
+/// Returns the pathname of the configuration file,
+/// `$XDG_CONFIG_HOME/futils/config` or `$HOME/.config/futils/config`.
+fn config_pathname() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|c| !c.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".config")))?;
+    Some(config.join("futils/config"))
+}
+
+/// Returns the name of the environment variable that sets the default for the
+/// option with the long name `name`. For example, `record-delimiter` is
+/// `FUTILS_RECORD_DELIMITER`.
+fn variable_name(name: &str) -> String {
+    format!("FUTILS_{}", name.to_uppercase().replace('-', "_"))
+}
+
+/// Sets the default for the option with the long name `name` to `value`, if
+/// `program` accepts it (according to `spec`). `source` says where the setting
+/// came from, for error messages.
+fn apply(options: &mut Options, spec: &str, name: &str, value: &str, source: &str) -> EmptyResult {
+    let Some(&(short, _, _)) = OPTION_SPECS
+        .iter()
+        .find(|o| o.1 == name && CONFIGURABLE.contains(o.0))
+    else {
+        return Err(UsageError::new(&format!("{source}: cannot configure \"{name}\"")).into());
+    };
+    if !spec_options(spec).iter().any(|o| o.0 == short) {
+        return Ok(());
+    }
+    if short == 'S' {
+        options.case_sensitive = match value {
+            "true" | "yes" | "1" => true,
+            "false" | "no" | "0" => false,
+            _ => {
+                return Err(UsageError::new(&format!(
+                    "{source}: \"{name}\" must be true or false, not \"{value}\""
+                ))
+                .into());
+            }
+        };
+        return Ok(());
+    }
+    set_option(options, short, Some(value)).map_err(|e| format!("{source}: {e}").into())
+}
+
+/// Applies the settings in `text` (the contents of the configuration file
+/// `pathname`) that are global or in the `[program]` section.
+fn apply_config(
+    options: &mut Options,
+    program: &str,
+    spec: &str,
+    pathname: &Path,
+    text: &str,
+) -> EmptyResult {
+    let mut in_section = true;
+    for (i, line) in text.lines().enumerate() {
+        let source = format!("{}:{}", pathname.display(), i + 1);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_section = section.trim() == program;
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(UsageError::new(&format!("{source}: expected name = value")).into());
+        };
+        if in_section {
+            apply(options, spec, name.trim(), value.trim(), &source)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the defaults in `options` for `program`, which accepts the options in
+/// `spec`, from the configuration file and then from the environment. The
+/// command line overrides both, and the environment overrides the file.
+pub fn configure(options: &mut Options, program: &str, spec: &str) -> EmptyResult {
+    if let Some(pathname) = config_pathname() {
+        match read_to_string(&pathname) {
+            Ok(text) => apply_config(options, program, spec, &pathname, &text)?,
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => return Err(format!("{}: {error}", pathname.display()).into()),
+        }
+    }
+
+    for &(short, name, _) in OPTION_SPECS {
+        if !CONFIGURABLE.contains(short) {
+            continue;
+        }
+        let variable = variable_name(name);
+        if let Some(value) = env::var_os(&variable) {
+            let Some(value) = value.to_str() else {
+                return Err(UsageError::new(&format!("{variable}: not valid UTF-8")).into());
+            };
+            apply(options, spec, name, value, &variable)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_apply_config() {
+    let mut options = Options::with_defaults().unwrap();
+    let text = "# Comment\nrecord-delimiter = \\x00\n\n[files]\ntypes = f\n[sort]\ncase-sensitive = true\n";
+    apply_config(&mut options, "files", "rSt", Path::new("config"), text).unwrap();
+    assert!(options.input_record_delimiter.is_match(b"\0"));
+    assert!(!options.input_record_delimiter.is_match(b"\n"));
+    assert_eq!("f", options.file_types);
+    assert!(!options.case_sensitive);
+
+    let mut options = Options::with_defaults().unwrap();
+    apply_config(&mut options, "sort", "S", Path::new("config"), text).unwrap();
+    assert!(options.input_record_delimiter.is_match(b"\n"));
+    assert!(options.case_sensitive);
+
+    let mut options = Options::with_defaults().unwrap();
+    assert!(
+        apply_config(
+            &mut options,
+            "sort",
+            "S",
+            Path::new("config"),
+            "help = true"
+        )
+        .is_err()
+    );
+    assert!(apply_config(&mut options, "sort", "S", Path::new("config"), "oops").is_err());
+}
@@ -0,0 +1,48 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reading records from CSV streams.
+
+use csv::{ByteRecord, ReaderBuilder};
+use std::io::Read;
+
+/// Returns an iterator over the records in the CSV stream `reader`, each as a
+/// list of fields. Fields can be quoted (`"a, b"`), contain quotes (as `""`),
+/// and span lines. Records need not all have the same number of fields.
+pub fn csv_records(
+    reader: &mut dyn Read,
+) -> impl Iterator<Item = Result<Vec<Vec<u8>>, csv::Error>> + '_ {
+    ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader)
+        .into_byte_records()
+        .map(|r| r.map(|record| fields(&record)))
+}
+
+fn fields(record: &ByteRecord) -> Vec<Vec<u8>> {
+    record.iter().map(<[u8]>::to_vec).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(input: &str) -> Vec<Vec<Vec<u8>>> {
+        let mut input = input.as_bytes();
+        csv_records(&mut input).map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_csv_records() {
+        assert_eq!(
+            vec![
+                vec![b"goat".to_vec(), b"grass, moss".to_vec()],
+                vec![b"sheep".to_vec(), b"\"more\"\ngrass".to_vec(), Vec::new()],
+                vec![b"llama".to_vec()],
+            ],
+            records("goat,\"grass, moss\"\nsheep,\"\"\"more\"\"\ngrass\",\r\nllama\n")
+        );
+        assert!(records("").is_empty());
+    }
+}
@@ -1,7 +1,16 @@
 // Copyright 2023 by [Chris Palmer](https://noncombatant.org)
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::util::serialize_str_or_bytes;
+use bstr::BStr;
 use serde::Serialize;
+use std::{
+    ffi::{CStr, CString, OsStr},
+    io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    ptr, slice,
+};
 
 pub type LinkCount = u16;
 pub type DeviceNumber = i32;
@@ -10,7 +19,8 @@ pub type BlockSize = i32;
 
 #[derive(Serialize)]
 pub struct Status<'a> {
-    pub name: &'a str,
+    #[serde(serialize_with = "serialize_str_or_bytes")]
+    pub name: &'a BStr,
     pub file_type: String,
     pub size: i64,
     pub modified_time: String,
@@ -27,3 +37,35 @@ pub struct Status<'a> {
     pub blocks: i64,
     pub block_size: BlockSize,
 }
+
+/// Renames `from` to `to`, failing with `EEXIST` rather than replacing `to` if
+/// it exists. Fails with `ENOTSUP` if the filesystem does not support that.
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    let from = CString::new(from.as_os_str().as_bytes())?;
+    let to = CString::new(to.as_os_str().as_bytes())?;
+    let result = unsafe { libc::renamex_np(from.as_ptr(), to.as_ptr(), libc::RENAME_EXCL) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Returns the mount points of all mounted filesystems.
+pub fn mount_points() -> io::Result<Vec<PathBuf>> {
+    let mut mounts: *mut libc::statfs = ptr::null_mut();
+    let count = unsafe { libc::getmntinfo(&raw mut mounts, libc::MNT_NOWAIT) };
+    match usize::try_from(count) {
+        Ok(count) if count > 0 => {
+            let mounts = unsafe { slice::from_raw_parts(mounts, count) };
+            Ok(mounts
+                .iter()
+                .map(|m| {
+                    let name = unsafe { CStr::from_ptr(m.f_mntonname.as_ptr()) };
+                    PathBuf::from(OsStr::from_bytes(name.to_bytes()))
+                })
+                .collect())
+        }
+        _ => Err(io::Error::last_os_error()),
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transparent decompression of input streams.
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use std::{
+    io::{self, Cursor, ErrorKind, Read},
+    iter::zip,
+};
+use xz2::read::XzDecoder;
+
+/// The compression formats that `decompress` recognizes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Compression {
+    Bzip2,
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+/// The magic bytes that begin each compression format. In bzip2’s, `#` stands
+/// for the block size, `1` through `9`, and it is followed by the magic of
+/// either the first block or (if the stream is empty) the end of the stream.
+const MAGIC: &[(&[u8], Compression)] = &[
+    (b"BZh#1AY&SY", Compression::Bzip2),
+    (b"BZh#\x17rE8P\x90", Compression::Bzip2),
+    (b"\x1f\x8b", Compression::Gzip),
+    (b"\xfd7zXZ\x00", Compression::Xz),
+    (b"\x28\xb5\x2f\xfd", Compression::Zstd),
+];
+
+/// The length of the longest `MAGIC`.
+const MAGIC_LENGTH: usize = 10;
+
+/// Returns true if `bytes` matches `magic` as far as both go.
+fn matches_magic(magic: &[u8], bytes: &[u8]) -> bool {
+    zip(magic, bytes).all(|(m, b)| {
+        if *m == b'#' {
+            (b'1'..=b'9').contains(b)
+        } else {
+            m == b
+        }
+    })
+}
+
+/// Returns the compression format that `header` begins with, if any.
+fn detect(header: &[u8]) -> Option<Compression> {
+    MAGIC
+        .iter()
+        .find(|(magic, _)| header.len() >= magic.len() && matches_magic(magic, header))
+        .map(|(_, c)| *c)
+}
+
+/// Returns true if `header` is too short to tell whether it begins with a
+/// `MAGIC`, i.e. if it is the start of one but not all of it.
+fn undecided(header: &[u8]) -> bool {
+    detect(header).is_none()
+        && MAGIC
+            .iter()
+            .any(|(magic, _)| header.len() < magic.len() && matches_magic(magic, header))
+}
+
+/// Returns true if `bytes` begins with the magic bytes of a compression format
+/// that `decompress` recognizes.
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    detect(bytes).is_some()
+}
+
+/// Reads the start of `read`, enough to `detect` its compression format. A
+/// single `read` usually suffices; it reads again only while what it has is
+/// `undecided` (as from a pipe that delivers the header in pieces). So it does
+/// not wait for more input than a person has typed at a terminal, unless what
+/// they typed so far could be the start of a compressed stream.
+fn read_header(read: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let mut header = vec![0; MAGIC_LENGTH];
+    let mut length = 0;
+    while length < MAGIC_LENGTH && (length == 0 || undecided(&header[..length])) {
+        match read.read(&mut header[length..]) {
+            Ok(0) => break,
+            Ok(n) => length += n,
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    header.truncate(length);
+    Ok(header)
+}
+
+/// Returns a `Read` that decompresses `read`, if it is compressed with bzip2,
+/// gzip, xz, or zstd. (The format is detected by the magic bytes at the start
+/// of the stream, not by the pathname's extension.) Otherwise, returns a
+/// `Read` of `read` as it is.
+pub fn decompress(mut read: Box<dyn Read>) -> io::Result<Box<dyn Read>> {
+    let header = read_header(&mut read)?;
+    let compression = detect(&header);
+    let read = Cursor::new(header).chain(read);
+    Ok(match compression {
+        None => Box::new(read),
+        Some(Compression::Bzip2) => Box::new(MultiBzDecoder::new(read)),
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(read)),
+        Some(Compression::Xz) => Box::new(XzDecoder::new_multi_decoder(read)),
+        Some(Compression::Zstd) => Box::new(zstd::stream::read::Decoder::new(read)?),
+    })
+}
+
+#[test]
+fn test_decompress() {
+    use flate2::{Compression as Level, write::GzEncoder};
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+    encoder.write_all(b"goat\nsheep\n").unwrap();
+    let compressed = encoder.finish().unwrap();
+    assert_eq!(Some(Compression::Gzip), detect(&compressed));
+
+    let mut text = String::new();
+    decompress(Box::new(Cursor::new(compressed)))
+        .unwrap()
+        .read_to_string(&mut text)
+        .unwrap();
+    assert_eq!("goat\nsheep\n", text);
+
+    let mut text = String::new();
+    decompress(Box::new(Cursor::new(b"go")))
+        .unwrap()
+        .read_to_string(&mut text)
+        .unwrap();
+    assert_eq!("go", text);
+}
+
+#[test]
+fn test_detect() {
+    assert_eq!(Some(Compression::Bzip2), detect(b"BZh91AY&SY\x01"));
+    assert_eq!(Some(Compression::Bzip2), detect(b"BZh1\x17rE8P\x90"));
+    assert_eq!(None, detect(b"BZh91AY&S"));
+    assert_eq!(None, detect(b"BZhello, world"));
+    assert_eq!(None, detect(b"BZh01AY&SY"));
+    assert!(undecided(b"BZh9"));
+    assert!(undecided(b"\x1f"));
+    assert!(!undecided(b"BZhe"));
+    assert!(!undecided(b"\x1f\x8b"));
+    assert!(!undecided(b"goat"));
+}
+
+#[test]
+fn test_read_header() {
+    /// Returns its chunks, 1 per `read`, and then fails, as if waiting for
+    /// input that never comes.
+    struct Chunks(Vec<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::other("would block"));
+            }
+            let chunk = self.0.remove(0);
+            buffer[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    assert_eq!(
+        b"go".to_vec(),
+        read_header(&mut Chunks(vec![b"go"])).unwrap()
+    );
+    assert_eq!(
+        b"\x1f\x8b".to_vec(),
+        read_header(&mut Chunks(vec![b"\x1f", b"\x8b"])).unwrap()
+    );
+    assert_eq!(
+        b"BZh91AY&SY".to_vec(),
+        read_header(&mut Chunks(vec![b"BZ".as_slice(), b"h91AY", b"&SY"])).unwrap()
+    );
+    assert_eq!(
+        b"BZhe".to_vec(),
+        read_header(&mut Chunks(vec![b"BZ", b"he"])).unwrap()
+    );
+}
@@ -1,50 +1,50 @@
 // Copyright 2023 by [Chris Palmer](https://noncombatant.org)
 // SPDX-License-Identifier: Apache-2.0
 
+use std::borrow::Cow;
 use std::io::{Error, Write};
 
+use bstr::BStr;
 use serde::Serialize;
 
+use crate::output::{StructuredWrite, number_field, record_fields};
 use crate::shell::Options;
 use crate::util::serialize_str_or_bytes;
 
 #[derive(Serialize)]
 pub struct EnumeratedRecord<'a> {
     pub n: Option<usize>,
-    pub pathname: &'a str,
     #[serde(serialize_with = "serialize_str_or_bytes")]
-    pub r: Vec<u8>,
+    pub pathname: &'a BStr,
+    /// Borrowed if the record is a slice of a memory-mapped file; see
+    /// `shell::file_records`.
+    #[serde(serialize_with = "serialize_str_or_bytes")]
+    pub r: Cow<'a, [u8]>,
+    /// The fields of `r`, if the input format delimited them; see
+    /// `shell::Record`.
+    #[serde(skip)]
+    pub fields: Option<Vec<Vec<u8>>>,
 }
 
-impl EnumeratedRecord<'_> {
-    pub fn write_columns(&self, output: &mut dyn Write, options: &Options) -> Result<(), Error> {
-        if options.print_empty || !self.r.is_empty() {
-            if let Some(n) = self.n {
-                output.write_all(self.pathname.as_bytes())?;
-                output.write_all(&options.output_field_delimiter)?;
-                write!(output, "{:>5}", n + 1)?;
-                output.write_all(&options.output_field_delimiter)?;
-            }
-            output.write_all(&self.r)?;
-            output.write_all(&options.output_record_delimiter)?;
+impl StructuredWrite for EnumeratedRecord<'_> {
+    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> Result<(), Error> {
+        if let Some(n) = self.n {
+            output.write_all(self.pathname)?;
+            output.write_all(&options.output_field_delimiter)?;
+            write!(output, "{:>5}", n + 1)?;
+            output.write_all(&options.output_field_delimiter)?;
         }
-        Ok(())
+        output.write_all(&self.r)?;
+        output.write_all(&options.output_record_delimiter)
     }
 
-    pub fn write_json(
-        &self,
-        output: &mut dyn Write,
-        pretty: bool,
-        options: &Options,
-    ) -> Result<(), Error> {
-        if options.print_empty || !self.r.is_empty() {
-            let to_json = if pretty {
-                serde_json::to_writer_pretty
-            } else {
-                serde_json::to_writer
-            };
-            to_json(output, &self)?;
+    fn to_fields(&self, options: &Options) -> Vec<Cow<'_, [u8]>> {
+        let mut fields: Vec<Cow<'_, [u8]>> = Vec::new();
+        if let Some(n) = self.n {
+            fields.push(Cow::Borrowed(self.pathname.as_ref()));
+            fields.push(number_field(n + 1));
         }
-        Ok(())
+        fields.extend(record_fields(&self.r, self.fields.as_deref(), options));
+        fields
     }
 }
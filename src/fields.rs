@@ -4,24 +4,35 @@
 //! The `futils fields` command.
 
 use crate::{
-    shell::{FileOpener, Options, STDIN_PATHNAME, ShellResult, parse_options},
-    util::{exit_with_result, help},
+    output::{OutputFormat, StructuredWrite, StructuredWriter, number_field},
+    records::HeaderMode,
+    shell::{
+        FileOpener, Options, STDIN_PATHNAME, ShellResult, UsageError, file_records, new_regex,
+        parse_options,
+    },
+    util::{StrOrBytes, exit_with_result, help},
 };
-use once_cell::sync::Lazy;
+use bstr::BStr;
 use regex::bytes::Regex;
-use regex_splitter::RegexSplitter;
-use serde::Serialize;
+use serde::{Serialize, Serializer, ser::SerializeStruct};
 use std::{
-    io::{Error, IsTerminal, Read, Write, stdout},
+    borrow::Cow,
+    error,
+    ffi::OsString,
+    io::{Error, Read, Write, stdout},
     num::ParseIntError,
+    sync::LazyLock,
 };
 
 pub const FIELDS_HELP: &str = include_str!("fields.md");
 pub const FIELDS_HELP_VERBOSE: &str = include_str!("fields_verbose.md");
 
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "aCcFfHhIJjmnoRrSsvZ";
+
 /// Returns the index of the first byte that is not a space character.
 fn first_non_space(record: &[u8]) -> Option<usize> {
-    static SPACE_CADET: Lazy<Regex> = Lazy::new(|| Regex::new(r"\S").unwrap());
+    static SPACE_CADET: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\S").unwrap());
     SPACE_CADET.find(record).map(|m| m.start())
 }
 
@@ -42,7 +53,7 @@ fn first_non_space(record: &[u8]) -> Option<usize> {
 /// (There's probably a more efficient way to do it. However, both n and m are
 /// likely to be small.)
 #[allow(clippy::cast_possible_wrap)] // Checked below.
-fn select_fields<'a>(fields: &[&'a [u8]], requested: &[isize], invert: bool) -> Vec<&'a [u8]> {
+pub fn select_fields<'a>(fields: &[&'a [u8]], requested: &[isize], invert: bool) -> Vec<&'a [u8]> {
     let mut result: Vec<&'a [u8]> = vec![];
     assert!(isize::try_from(fields.len()).is_ok());
     let length = fields.len() as isize;
@@ -92,105 +103,560 @@ fn test_select_fields() {
     assert_eq!(expected, result);
 }
 
+/// Parses the `-c` options in `fields` as field indices.
+pub fn parse_fields(fields: &[String]) -> Result<Vec<isize>, ParseIntError> {
+    fields.iter().map(|f| str::parse::<isize>(f)).collect()
+}
+
+/// A field requested with `-c`: an index, a range of indices (`start..end`),
+/// the name of a field in the header (see `-H`), or a regular expression
+/// (`/regex/`) that matches the names of fields in the header.
+#[derive(Debug)]
+enum Field {
+    Index(isize),
+    Range(Option<isize>, Option<isize>),
+    Name(String),
+    Pattern(Regex),
+}
+
+impl Field {
+    fn parse(s: &str, options: &Options) -> Result<Self, regex::Error> {
+        if let Ok(n) = s.parse::<isize>() {
+            return Ok(Self::Index(n));
+        }
+        if let Some((start, end)) = s.split_once("..") {
+            let bound = |b: &str| {
+                if b.is_empty() {
+                    Ok(None)
+                } else {
+                    b.parse::<isize>().map(Some)
+                }
+            };
+            if let (Ok(start), Ok(end)) = (bound(start), bound(end)) {
+                return Ok(Self::Range(start, end));
+            }
+        }
+        let pattern = s.strip_prefix('/').and_then(|s| s.strip_suffix('/'));
+        Ok(match pattern {
+            Some(pattern) => Self::Pattern(new_regex(pattern, options)?),
+            None => Self::Name(s.to_string()),
+        })
+    }
+
+    /// Parses a comma-separated list of fields, such as `0,3..5,-1`. Commas
+    /// inside a `/regex/` do not separate fields.
+    fn parse_list(s: &str, options: &Options) -> Result<Vec<Self>, regex::Error> {
+        let mut parts = Vec::new();
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            let part = &s[start..i];
+            if c == ',' && (!part.starts_with('/') || (part.len() > 1 && part.ends_with('/'))) {
+                parts.push(part);
+                start = i + 1;
+            }
+        }
+        parts.push(&s[start..]);
+        parts
+            .into_iter()
+            .filter(|p| !p.is_empty())
+            .map(|p| Self::parse(p, options))
+            .collect()
+    }
+
+    /// Returns true if the field is given by position, rather than by name.
+    const fn is_positional(&self) -> bool {
+        matches!(self, Self::Index(_) | Self::Range(..))
+    }
+}
+
+/// A field index or range of indices, as resolved from a `Field`. A range
+/// can be resolved to indices only once we know how many fields a record has;
+/// see `expand`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Selection {
+    Index(isize),
+    Range(Option<isize>, Option<isize>),
+}
+
+/// Returns the `selections` resolved from the `requested` fields, looking up
+/// names and patterns in `header`. A pattern selects every field whose name it
+/// matches.
+#[allow(clippy::cast_possible_wrap)] // Slice lengths fit in `isize`.
+fn resolve_fields(requested: &[Field], header: &[&[u8]]) -> Result<Vec<Selection>, String> {
+    let mut selections = Vec::with_capacity(requested.len());
+    for field in requested {
+        match field {
+            Field::Index(n) => selections.push(Selection::Index(*n)),
+            Field::Range(start, end) => selections.push(Selection::Range(*start, *end)),
+            Field::Name(name) => match header.iter().position(|h| *h == name.as_bytes()) {
+                Some(i) => selections.push(Selection::Index(i as isize)),
+                None => return Err(format!("no field named \"{name}\"")),
+            },
+            Field::Pattern(pattern) => {
+                let count = selections.len();
+                selections.extend(
+                    (0..header.len())
+                        .filter(|i| pattern.is_match(header[*i]))
+                        .map(|i| Selection::Index(i as isize)),
+                );
+                if selections.len() == count {
+                    return Err(format!("no field name matches /{}/", pattern.as_str()));
+                }
+            }
+        }
+    }
+    Ok(selections)
+}
+
+/// Returns the indices that `selections` select in a record of `length`
+/// fields, for `select_fields`. Ranges are half-open, as in Rust, and their
+/// negative bounds count from the end of the record: `1..-1` is every field but
+/// the first and the last. Individual indices are left as they are, since
+/// `select_fields` handles negative indices itself.
+#[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)] // Checked below.
+fn expand(selections: &[Selection], length: usize) -> Vec<isize> {
+    let absolute = |i: isize| {
+        if i < 0 {
+            length.saturating_sub(i.unsigned_abs())
+        } else {
+            length.min(i as usize)
+        }
+    };
+    let mut indices = Vec::with_capacity(selections.len());
+    for s in selections {
+        match *s {
+            Selection::Index(n) => indices.push(n),
+            Selection::Range(start, end) => {
+                let start = start.map_or(0, absolute);
+                let end = end.map_or(length, absolute);
+                indices.extend((start..end).map(|i| i as isize));
+            }
+        }
+    }
+    indices
+}
+
+#[test]
+fn test_resolve_fields() {
+    let header: Vec<&[u8]> = vec![b"Count", b"Animal", b"Color"];
+    let requested = [
+        Field::Name(String::from("Animal")),
+        Field::Index(-1),
+        Field::Pattern(Regex::new("^Co").unwrap()),
+    ];
+    assert_eq!(
+        Ok(vec![
+            Selection::Index(1),
+            Selection::Index(-1),
+            Selection::Index(0),
+            Selection::Index(2)
+        ]),
+        resolve_fields(&requested, &header)
+    );
+    assert!(resolve_fields(&[Field::Name(String::from("Diet"))], &header).is_err());
+    assert!(resolve_fields(&[Field::Pattern(Regex::new("x").unwrap())], &header).is_err());
+}
+
+#[test]
+fn test_parse_fields_list() {
+    let options = Options::with_defaults().unwrap();
+    let fields = Field::parse_list("0,2..5,-3..,Name,/a,b/,..-2", &options).unwrap();
+    assert_eq!(6, fields.len());
+    assert!(matches!(fields[0], Field::Index(0)));
+    assert!(matches!(fields[1], Field::Range(Some(2), Some(5))));
+    assert!(matches!(fields[2], Field::Range(Some(-3), None)));
+    assert!(matches!(&fields[3], Field::Name(name) if name == "Name"));
+    assert!(matches!(&fields[4], Field::Pattern(p) if p.as_str() == "a,b"));
+    assert!(matches!(fields[5], Field::Range(None, Some(-2))));
+}
+
+#[test]
+fn test_expand() {
+    let range = |start, end| Selection::Range(start, end);
+    assert_eq!(vec![2, 3, 4], expand(&[range(Some(2), Some(5))], 6));
+    assert_eq!(vec![4, 5], expand(&[range(Some(4), None)], 6));
+    assert_eq!(vec![0, 1, 2, 3], expand(&[range(None, Some(-2))], 6));
+    assert_eq!(vec![3, 4, 5], expand(&[range(Some(-3), None)], 6));
+    assert_eq!(vec![0, 1], expand(&[range(Some(-3), None)], 2));
+    assert_eq!(Vec::<isize>::new(), expand(&[range(Some(4), Some(2))], 6));
+    assert_eq!(
+        vec![-1, 0, 1, 0],
+        expand(
+            &[
+                Selection::Index(-1),
+                range(None, Some(2)),
+                Selection::Index(0)
+            ],
+            6
+        )
+    );
+}
+
+/// Splits `record` into fields with `-f`, skipping leading space if `-s`.
+fn split_fields<'a>(record: &'a [u8], options: &Options) -> Vec<&'a [u8]> {
+    let start = if options.skip {
+        first_non_space(record).unwrap_or(0)
+    } else {
+        0
+    };
+    options
+        .input_field_delimiter
+        .split(&record[start..])
+        .collect()
+}
+
+/// Returns the fields that `pattern` captures in `record`: the capture groups of
+/// the first match (or, if `all`, of each match), or the whole match if
+/// `pattern` has no groups. Groups that did not participate in the match are
+/// empty.
+fn extract<'a>(pattern: &Regex, record: &'a [u8], all: bool) -> Vec<Vec<&'a [u8]>> {
+    let groups = if pattern.captures_len() > 1 {
+        1..pattern.captures_len()
+    } else {
+        0..1
+    };
+    let captures = pattern.captures_iter(record).map(|c| {
+        groups
+            .clone()
+            .map(|i| c.get(i).map_or(&[][..], |m| m.as_bytes()))
+            .collect()
+    });
+    if all {
+        captures.collect()
+    } else {
+        captures.take(1).collect()
+    }
+}
+
+/// Returns the names of the fields that `extract` returns for `pattern`: the
+/// names of the capture groups, or their numbers if they have no names.
+/// Returns `None` if no group has a name.
+fn capture_names(pattern: &Regex) -> Option<Vec<Vec<u8>>> {
+    if pattern.capture_names().all(|n| n.is_none()) {
+        return None;
+    }
+    Some(
+        pattern
+            .capture_names()
+            .enumerate()
+            .skip(1)
+            .map(|(i, n)| {
+                n.map_or_else(|| i.to_string(), ToString::to_string)
+                    .into_bytes()
+            })
+            .collect(),
+    )
+}
+
+#[test]
+fn test_extract() {
+    let pattern = Regex::new(r"(?P<key>\w+): (\d+)?").unwrap();
+    let expected: Vec<Vec<&[u8]>> = vec![vec![b"goats", b"4"]];
+    assert_eq!(expected, extract(&pattern, b"goats: 4, sheep: ", false));
+    let expected: Vec<Vec<&[u8]>> = vec![vec![b"goats", b"4"], vec![b"sheep", b""]];
+    assert_eq!(expected, extract(&pattern, b"goats: 4, sheep: ", true));
+    assert!(extract(&pattern, b"llamas", true).is_empty());
+    assert_eq!(
+        Some(vec![b"key".to_vec(), b"2".to_vec()]),
+        capture_names(&pattern)
+    );
+
+    let pattern = Regex::new(r"\d+").unwrap();
+    let expected: Vec<Vec<&[u8]>> = vec![vec![b"4"], vec![b"12"]];
+    assert_eq!(expected, extract(&pattern, b"4 goats, 12 sheep", true));
+    assert_eq!(None, capture_names(&pattern));
+}
+
 // TODO: Consider folding this into enumerated_record.rs?
-#[derive(Serialize)]
 struct EnumeratedRecord<'a> {
     n: Option<usize>,
-    pathname: &'a str,
+    pathname: &'a BStr,
     fields: Vec<&'a [u8]>,
+    /// The names of the `fields`, from the header (if any).
+    names: Option<&'a [Vec<u8>]>,
+    /// The indices of the `fields`, if they were selected with `-c`.
+    indices: Vec<isize>,
 }
 
-impl<'a> EnumeratedRecord<'a> {
-    fn new(
-        n: Option<usize>,
-        pathname: &'a str,
-        record: &'a [u8],
-        requested_fields: &[isize],
-        options: &Options,
-    ) -> Self {
-        let mut start = 0;
-        if options.skip {
-            if let Some(s) = first_non_space(record) {
-                start = s;
-            }
-        };
-        let mut fields = options
-            .input_field_delimiter
-            .split(&record[start..])
-            .collect::<Vec<&[u8]>>();
-        if !requested_fields.is_empty() {
-            fields = select_fields(&fields, requested_fields, options.invert_fields);
-        }
-        EnumeratedRecord {
-            n,
-            pathname,
-            fields,
+/// The fields of a record, as a JSON object keyed by their names. Fields that
+/// have no name are keyed by their position.
+struct NamedFields<'a, 'b> {
+    names: &'b [Vec<u8>],
+    fields: &'b [&'a [u8]],
+}
+
+impl Serialize for NamedFields<'_, '_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(self.fields.iter().enumerate().map(|(i, f)| {
+            let name = self.names.get(i).map_or_else(
+                || i.to_string(),
+                |n| String::from_utf8_lossy(n).into_owned(),
+            );
+            (name, StrOrBytes(f))
+        }))
+    }
+}
+
+impl Serialize for EnumeratedRecord<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut record = serializer.serialize_struct("EnumeratedRecord", 3)?;
+        record.serialize_field("n", &self.n)?;
+        record.serialize_field("pathname", &StrOrBytes(self.pathname))?;
+        match self.names {
+            Some(names) => record.serialize_field(
+                "fields",
+                &NamedFields {
+                    names,
+                    fields: &self.fields,
+                },
+            )?,
+            None => record.serialize_field("fields", &self.fields)?,
         }
+        record.end()
     }
+}
 
+impl StructuredWrite for EnumeratedRecord<'_> {
     fn write_columns(&self, output: &mut dyn Write, options: &Options) -> Result<(), Error> {
-        if !self.fields.is_empty() {
-            if let Some(n) = self.n {
-                output.write_all(self.pathname.as_bytes())?;
-                output.write_all(&options.output_field_delimiter)?;
-                write!(output, "{:>5}", n + 1)?;
+        if let Some(n) = self.n {
+            output.write_all(self.pathname)?;
+            output.write_all(&options.output_field_delimiter)?;
+            write!(output, "{:>5}", n + 1)?;
+            output.write_all(&options.output_field_delimiter)?;
+        }
+        for (n, f) in self.fields.iter().enumerate() {
+            output.write_all(f)?;
+            if n != self.fields.len() - 1 {
                 output.write_all(&options.output_field_delimiter)?;
             }
-            for (n, f) in self.fields.iter().enumerate() {
-                output.write_all(f)?;
-                if n != self.fields.len() - 1 {
-                    output.write_all(&options.output_field_delimiter)?;
-                }
-            }
-            output.write_all(&options.output_record_delimiter)?;
         }
-        Ok(())
+        output.write_all(&options.output_record_delimiter)
     }
 
-    fn write_json(&self, output: &mut dyn Write, pretty: bool) -> Result<(), Error> {
-        if !self.fields.is_empty() {
-            let to_json = if pretty {
-                serde_json::to_writer_pretty
-            } else {
-                serde_json::to_writer
-            };
-            to_json(output, &self)?;
+    fn to_fields(&self, _: &Options) -> Vec<Cow<'_, [u8]>> {
+        let mut fields: Vec<Cow<'_, [u8]>> = Vec::new();
+        if let Some(n) = self.n {
+            fields.push(Cow::Borrowed(self.pathname.as_ref()));
+            fields.push(number_field(n + 1));
         }
-        Ok(())
+        fields.extend(self.fields.iter().map(|f| Cow::Borrowed(*f)));
+        fields
+    }
+
+    /// The headers are the names of the fields from the header record (if
+    /// any), the indices of the fields selected with `-c`, or (if there are
+    /// none) the positions of the fields.
+    fn headers(&self, options: &Options) -> Vec<String> {
+        let mut headers = Vec::new();
+        if self.n.is_some() {
+            headers.push(String::from("Pathname"));
+            headers.push(String::from("Record"));
+        }
+        if let Some(names) = self.names {
+            headers.extend(
+                names
+                    .iter()
+                    .map(|n| String::from_utf8_lossy(n).into_owned()),
+            );
+        } else if options.fields.is_empty() {
+            headers.extend((0..self.fields.len()).map(|i| i.to_string()));
+        } else if !options.invert_fields {
+            headers.extend(self.indices.iter().map(ToString::to_string));
+        }
+        headers
+    }
+}
+
+/// Returns the `requested` fields, or all `fields` if none are requested, and
+/// the indices they were selected by. `width` is the number of fields in the
+/// header, if there is one: then ranges and negative indices count to `width`,
+/// and a requested field that the record lacks is empty, so that the fields
+/// line up with the header’s names.
+#[allow(clippy::cast_possible_wrap)] // Checked below.
+fn select_requested<'a>(
+    fields: &[&'a [u8]],
+    width: Option<usize>,
+    requested: &[Selection],
+    options: &Options,
+) -> (Vec<&'a [u8]>, Vec<isize>) {
+    if requested.is_empty() {
+        return (fields.to_vec(), Vec::new());
+    }
+    let indices = expand(requested, width.unwrap_or(fields.len()));
+    let Some(width) = width.filter(|_| !options.invert_fields) else {
+        return (
+            select_fields(fields, &indices, options.invert_fields),
+            indices,
+        );
+    };
+    assert!(isize::try_from(width).is_ok());
+    let selected = indices
+        .iter()
+        .map(|&i| {
+            let i = if i < 0 { width as isize + i } else { i };
+            usize::try_from(i)
+                .ok()
+                .and_then(|i| fields.get(i))
+                .copied()
+                .unwrap_or_default()
+        })
+        .collect();
+    (selected, indices)
+}
+
+#[test]
+fn test_select_requested_short_record() {
+    let options = Options::with_defaults().unwrap();
+    // The header is `A,B,C`, and `-c C,A` selects fields 2 and 0.
+    let requested = [Selection::Index(2), Selection::Index(0)];
+    let empty: &[u8] = b"";
+
+    let fields: Vec<&[u8]> = vec![b"x"];
+    assert_eq!(
+        (vec![empty, b"x"], vec![2, 0]),
+        select_requested(&fields, Some(3), &requested, &options)
+    );
+    assert_eq!(
+        (vec![empty, empty], vec![2, 0]),
+        select_requested(&[], Some(3), &requested, &options)
+    );
+    // Without a header, missing fields are skipped.
+    assert_eq!(
+        (vec![b"x".as_slice()], vec![2, 0]),
+        select_requested(&fields, None, &requested, &options)
+    );
+
+    let fields: Vec<&[u8]> = vec![b"x", b"y"];
+    assert_eq!(
+        (vec![empty, b"y"], vec![-1, 1]),
+        select_requested(
+            &fields,
+            Some(3),
+            &[Selection::Index(-1), Selection::Index(1)],
+            &options
+        )
+    );
+    assert_eq!(
+        (vec![b"y".as_slice(), empty], vec![1, 2]),
+        select_requested(
+            &fields,
+            Some(3),
+            &[Selection::Range(Some(1), None)],
+            &options
+        )
+    );
+}
+
+/// Returns true if the header record should be printed: in the columns and
+/// CSV formats only, since the other formats name the fields themselves.
+const fn prints_header(options: &Options, header_written: bool) -> bool {
+    matches!(
+        options.output_format,
+        OutputFormat::Columns | OutputFormat::Csv
+    ) && match options.header {
+        Some(HeaderMode::Repeat) => true,
+        Some(HeaderMode::Pass) => !header_written,
+        Some(HeaderMode::Suppress) | None => false,
     }
 }
 
 fn print_fields(
+    map: Option<&[u8]>,
     reader: &mut dyn Read,
-    pathname: &str,
+    pathname: &BStr,
     options: &Options,
-    requested_fields: &[isize],
+    requested_fields: &[Field],
+    header_written: &mut bool,
+    output: &mut StructuredWriter,
 ) -> ShellResult {
-    let mut stdout = stdout();
-    for (n, r) in RegexSplitter::new(reader, &options.input_record_delimiter)
-        .map_while(Result::ok)
-        .enumerate()
-    {
-        let fields = EnumeratedRecord::new(
-            if options.no_enumerate { None } else { Some(n) },
+    // If there is a header, we can resolve the requested fields only once we
+    // have read it. With `-m`, the names of the capture groups are the header.
+    let pattern = options.match_expressions.first();
+    let mut names = pattern.and_then(capture_names);
+    let mut selected = if options.header.is_some() {
+        None
+    } else {
+        let header: Vec<&[u8]> = names.iter().flatten().map(Vec::as_slice).collect();
+        let selected = resolve_fields(requested_fields, &header)?;
+        let (header, _) = select_requested(&header, None, &selected, options);
+        let header = header.iter().map(|h| h.to_vec()).collect();
+        if names.is_some() {
+            names = Some(header);
+        }
+        Some(selected)
+    };
+    // The number of fields in the header, if any.
+    let mut header_width = None;
+    let mut n = 0;
+    let mut print = |fields: Vec<&[u8]>| -> Result<(), Box<dyn error::Error>> {
+        if selected.is_none() {
+            let requested = resolve_fields(requested_fields, &fields)?;
+            let (header, _) = select_requested(&fields, None, &requested, options);
+            if prints_header(options, *header_written) {
+                let mut row: Vec<&[u8]> = if options.no_enumerate {
+                    Vec::new()
+                } else {
+                    vec![b"Pathname".as_slice(), b"Record".as_slice()]
+                };
+                row.extend(&header);
+                output.write(&EnumeratedRecord {
+                    n: None,
+                    pathname,
+                    fields: row,
+                    names: None,
+                    indices: Vec::new(),
+                })?;
+                *header_written = true;
+            }
+            names = Some(header.iter().map(|h| h.to_vec()).collect());
+            header_width = Some(fields.len());
+            selected = Some(requested);
+            return Ok(());
+        }
+
+        let requested = selected.as_deref().unwrap_or_default();
+        let (fields, indices) = select_requested(&fields, header_width, requested, options);
+        let record = EnumeratedRecord {
+            n: if options.no_enumerate { None } else { Some(n) },
             pathname,
-            &r,
-            requested_fields,
-            options,
-        );
-        if options.json_output {
-            let t = stdout.is_terminal();
-            fields.write_json(&mut stdout, t)?;
-        } else {
-            fields.write_columns(&mut stdout, options)?;
+            fields,
+            names: names.as_deref(),
+            indices,
+        };
+        n += 1;
+        if !record.fields.is_empty() {
+            output.write(&record)?;
+        }
+        Ok(())
+    };
+
+    // A record is either whole, or already split into fields by `-C` or `-j`.
+    // With `-m`, we extract fields from the whole record, in which split
+    // records' fields are joined with `-F`.
+    let mut records = file_records(map, reader, pathname, options);
+    for record in records.by_ref() {
+        match (pattern, &record.fields) {
+            (None, None) => print(split_fields(&record.r, options))?,
+            (None, Some(fields)) => print(fields.iter().map(Vec::as_slice).collect())?,
+            (Some(pattern), _) => {
+                for fields in extract(pattern, &record.r, options.show_all) {
+                    print(fields)?;
+                }
+            }
         }
     }
-    Ok(0)
+    Ok(records.errors)
 }
 
 /// Runs the `fields` command on `arguments`.
-pub fn fields_main(arguments: &[String]) -> ShellResult {
-    let (options, arguments) = parse_options(arguments)?;
+pub fn fields_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
     if options.help {
         exit_with_result(help(
             0,
@@ -207,20 +673,39 @@ pub fn fields_main(arguments: &[String]) -> ShellResult {
         exit_with_result(help(-1, FIELDS_HELP, false, None));
     }
 
-    // TODO: To support named fields, use an `enum Field` here with `isize` and
-    // `String` variants.
-    let requested_fields = options
-        .fields
-        .iter()
-        .map(|f| str::parse::<isize>(f))
-        .collect::<Result<Vec<isize>, ParseIntError>>()?;
+    let mut requested_fields = Vec::new();
+    for f in &options.fields {
+        requested_fields.extend(Field::parse_list(f, &options)?);
+    }
+    if options.match_expressions.len() > 1 {
+        return Err(UsageError::new("fields takes at most 1 -m regex").into());
+    }
+    if options.header.is_some() && !options.match_expressions.is_empty() {
+        return Err(UsageError::new("fields cannot combine -H and -m").into());
+    }
+    if options.header.is_none()
+        && options.match_expressions.is_empty()
+        && !requested_fields.iter().all(Field::is_positional)
+    {
+        return Err(UsageError::new("fields can be selected by name only with -H or -m").into());
+    }
 
+    let mut output = StructuredWriter::new(stdout(), &options);
     let mut status = 0;
-    for file in FileOpener::new(arguments) {
+    let mut header_written = false;
+    for file in FileOpener::new(arguments, &options).mapped() {
         let pathname = file.pathname.unwrap_or(&STDIN_PATHNAME);
         match file.read {
-            Ok(mut read) => match print_fields(&mut read, pathname, &options, &requested_fields) {
-                Ok(_) => {}
+            Ok(mut read) => match print_fields(
+                file.map.as_deref(),
+                &mut read,
+                pathname,
+                &options,
+                &requested_fields,
+                &mut header_written,
+                &mut output,
+            ) {
+                Ok(errors) => status += errors,
                 Err(error) => {
                     eprintln!("{pathname}: {error}");
                     status += 1;
@@ -232,5 +717,6 @@ pub fn fields_main(arguments: &[String]) -> ShellResult {
             }
         }
     }
+    output.finish()?;
     Ok(status)
 }
@@ -4,31 +4,38 @@
 //! The `futils fileid` command.
 
 use crate::{
-    shell::{Options, ShellResult, StructuredWrite, parse_options},
-    util::{exit_with_result, help},
+    output::{StructuredWrite, StructuredWriter, number_field},
+    shell::{Options, ShellResult, parse_options},
+    util::{exit_with_result, help, serialize_str_or_bytes},
 };
 use base64ct::{Base64, Encoding};
 use blake3::Hasher;
 use serde::Serialize;
 use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
     fs,
     io::{Error, Write, copy, stdout},
-    os::unix::fs::MetadataExt,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
 };
 
 pub const FILEID_HELP: &str = include_str!("fileid.md");
 pub const FILEID_HELP_VERBOSE: &str = include_str!("fileid_verbose.md");
 
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "FhJoRv";
+
 #[derive(Serialize)]
 struct FileID {
     hash: Option<String>,
     device: u64,
     inode: u64,
     size: u64,
-    pathname: String,
+    #[serde(serialize_with = "serialize_str_or_bytes")]
+    pathname: Vec<u8>,
 }
 
-impl FileID {
+impl StructuredWrite for FileID {
     fn write_columns(&self, output: &mut dyn Write, options: &Options) -> Result<(), Error> {
         if let Some(hash) = &self.hash {
             write!(output, "{hash:<44}")?;
@@ -40,34 +47,33 @@ impl FileID {
         output.write_all(&options.output_field_delimiter)?;
         write!(output, "{:>9}", self.size)?;
         output.write_all(&options.output_field_delimiter)?;
-        output.write_all(self.pathname.as_bytes())?;
-        output.write_all(&options.output_record_delimiter)?;
-        Ok(())
+        output.write_all(&self.pathname)?;
+        output.write_all(&options.output_record_delimiter)
     }
 
-    fn write_json(&self, output: &mut dyn Write, pretty: bool) -> Result<(), Error> {
-        let to_json = if pretty {
-            serde_json::to_writer_pretty
-        } else {
-            serde_json::to_writer
-        };
-        to_json(output, &self)?;
-        Ok(())
+    fn to_fields(&self, _: &Options) -> Vec<Cow<'_, [u8]>> {
+        let mut fields = Vec::new();
+        if let Some(hash) = &self.hash {
+            fields.push(Cow::Borrowed(hash.as_bytes()));
+        }
+        for n in [self.device, self.inode, self.size] {
+            fields.push(number_field(n));
+        }
+        fields.push(Cow::Borrowed(self.pathname.as_slice()));
+        fields
     }
-}
 
-impl StructuredWrite for FileID {
-    fn write(&self, output: &mut dyn Write, options: &Options) -> Result<(), Error> {
-        if options.json_output {
-            // TODO: `pretty` should be a command-line switch.
-            self.write_json(output, true)
-        } else {
-            self.write_columns(output, options)
+    fn headers(&self, _: &Options) -> Vec<String> {
+        let mut headers = Vec::new();
+        if self.hash.is_some() {
+            headers.push("Hash");
         }
+        headers.extend(["Device", "Inode", "Size", "Pathname"]);
+        headers.into_iter().map(String::from).collect()
     }
 }
 
-fn get_fileid(pathname: &str, verbose: bool) -> std::io::Result<FileID> {
+fn get_fileid(pathname: &OsStr, verbose: bool) -> std::io::Result<FileID> {
     let mut file = fs::File::open(pathname)?;
     let metadata = file.metadata()?;
     Ok(FileID {
@@ -89,13 +95,13 @@ fn get_fileid(pathname: &str, verbose: bool) -> std::io::Result<FileID> {
         device: metadata.dev(),
         inode: metadata.ino(),
         size: metadata.len(),
-        pathname: pathname.to_string(),
+        pathname: pathname.as_bytes().to_vec(),
     })
 }
 
 /// Runs the `fileid` command on `arguments`.
-pub fn fileid_main(arguments: &[String]) -> ShellResult {
-    let (options, arguments) = parse_options(arguments)?;
+pub fn fileid_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
     if options.help {
         exit_with_result(help(
             0,
@@ -109,17 +115,17 @@ pub fn fileid_main(arguments: &[String]) -> ShellResult {
         ));
     }
 
+    let mut output = StructuredWriter::new(stdout(), &options);
     let mut status = 0;
     for pathname in arguments {
         match get_fileid(pathname, options.verbose) {
-            Ok(file_id) => {
-                file_id.write(&mut stdout(), &options)?;
-            }
+            Ok(file_id) => output.write(&file_id)?,
             Err(error) => {
-                eprintln!("{pathname}: {error}");
+                eprintln!("{}: {error}", pathname.to_string_lossy());
                 status += 1;
             }
         }
     }
+    output.finish()?;
     Ok(status)
 }
@@ -3,22 +3,31 @@
 
 //! The `futils files` command.
 
-use std::cmp::Ordering;
-use std::io::{Write, stdout};
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Write, stdout};
+use std::os::unix::ffi::OsStrExt;
 use std::time::SystemTime;
 
+use bstr::ByteSlice;
 use chrono::DateTime;
+use serde::Serialize;
 use walkdir::{DirEntry, WalkDir};
 
+use crate::output::{StructuredWrite, StructuredWriter};
+use crate::predicate::{Predicate, Term};
 use crate::shell::{Options, ShellResult, parse_options};
 use crate::time::Time;
-use crate::util::{exit_with_result, help, run_command};
+use crate::util::{exit_with_result, help, run_command, serialize_str_or_bytes};
 
 pub const FILES_HELP: &str = include_str!("files.md");
 pub const FILES_HELP_VERBOSE: &str = include_str!("files_verbose.md");
 
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "adEhJMmopRStvx";
+
 fn is_hidden(e: &DirEntry) -> bool {
-    e.path().to_str().is_some_and(|s| s.contains("/."))
+    e.path().as_os_str().as_bytes().contains_str("/.")
 }
 
 fn compare_times(e: &DirEntry, t: &Time) -> Result<bool, std::io::Error> {
@@ -29,16 +38,33 @@ fn compare_times(e: &DirEntry, t: &Time) -> Result<bool, std::io::Error> {
         .unwrap()
         .as_secs();
     let modified = DateTime::from_timestamp(modified.try_into().unwrap(), 0).unwrap();
-    let given = t.date_time.and_utc();
-    Ok(match t.ordering {
-        Ordering::Greater => given <= modified,
-        Ordering::Less => given >= modified,
-        Ordering::Equal => given == modified,
-    })
+    Ok(t.matches(&modified.naive_utc()))
+}
+
+/// A pathname that matched all the criteria.
+#[derive(Serialize)]
+struct Match<'a> {
+    #[serde(serialize_with = "serialize_str_or_bytes")]
+    pathname: &'a [u8],
 }
 
-fn print_matches(pathname: &str, options: &Options) -> ShellResult {
-    let mut stdout = stdout();
+impl StructuredWrite for Match<'_> {
+    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> Result<(), io::Error> {
+        output.write_all(self.pathname)?;
+        output.write_all(&options.output_record_delimiter)
+    }
+
+    fn to_fields(&self, _: &Options) -> Vec<Cow<'_, [u8]>> {
+        vec![Cow::Borrowed(self.pathname)]
+    }
+}
+
+fn print_matches(
+    pathname: &OsStr,
+    options: &Options,
+    predicate: &Predicate,
+    output: &mut StructuredWriter,
+) -> ShellResult {
     let mut it = if options.depth > 0 {
         WalkDir::new(pathname).max_depth(options.depth)
     } else {
@@ -47,10 +73,9 @@ fn print_matches(pathname: &str, options: &Options) -> ShellResult {
     .into_iter();
     let mut status = 0;
 
-    'outer: loop {
-        let entry = match it.next() {
-            None => break Ok(status),
-            Some(entry) => entry,
+    loop {
+        let Some(entry) = it.next() else {
+            break Ok(status);
         };
         let entry = match entry {
             Err(error) => {
@@ -79,63 +104,46 @@ fn print_matches(pathname: &str, options: &Options) -> ShellResult {
             continue;
         }
 
-        let path = entry.path();
-        let Some(pathname) = path.to_str() else {
-            eprintln!("pathname not valid Unicode: '{}'", path.display());
-            status += 1;
-            continue;
-        };
-
-        for re in &options.prune_expressions {
-            if re.is_match(pathname.as_bytes()) {
-                if entry.file_type().is_dir() {
-                    it.skip_current_dir();
-                }
-                continue 'outer;
-            }
-        }
+        // Pathnames can be any bytes, not necessarily UTF-8, so we match and
+        // print them as bytes.
+        let pathname = entry.path().as_os_str().as_bytes();
 
-        for re in &options.match_expressions {
-            if !re.is_match(pathname.as_bytes()) {
-                continue 'outer;
-            }
+        // Pruned directories are not searched at all. (`predicate` takes
+        // care of pruned files.)
+        if is_dir && predicate.prunes(pathname) {
+            it.skip_current_dir();
+            continue;
         }
 
-        for mtime in &options.mtime_expressions {
-            match compare_times(&entry, mtime) {
-                Ok(true) => continue,
-                Ok(false) => continue 'outer,
+        let matched = predicate.evaluate(&mut |term| match term {
+            Term::Match(re) => re.is_match(pathname),
+            Term::Prune(re) => !re.is_match(pathname),
+            Term::Mtime(mtime) => match compare_times(&entry, mtime) {
+                Ok(matched) => matched,
                 Err(error) => {
                     eprintln!("{error}");
                     status += 1;
-                    continue 'outer;
-                }
-            }
-        }
-
-        for command in &options.match_commands {
-            match run_command(command, &[pathname.as_bytes()], options.verbose) {
-                Ok(status) => {
-                    if status != 0 {
-                        continue 'outer;
-                    }
+                    false
                 }
+            },
+            Term::Command(command) => match run_command(command, &[pathname], options.verbose) {
+                Ok(code) => code == 0,
                 Err(error) => {
-                    eprintln!("{command} \"{pathname}\": {error}");
+                    eprintln!("{command} \"{}\": {error}", pathname.as_bstr());
                     status += 1;
-                    continue 'outer;
+                    false
                 }
-            }
+            },
+        });
+        if matched {
+            output.write(&Match { pathname })?;
         }
-
-        stdout.write_all(pathname.as_bytes())?;
-        stdout.write_all(&options.output_record_delimiter)?;
     }
 }
 
 /// Runs the `files` command on `arguments`.
-pub fn files_main(arguments: &[String]) -> ShellResult {
-    let (options, arguments) = parse_options(arguments)?;
+pub fn files_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
     if options.help {
         exit_with_result(help(
             0,
@@ -148,24 +156,23 @@ pub fn files_main(arguments: &[String]) -> ShellResult {
             },
         ));
     }
-    if options.json_input || options.json_output {
-        unimplemented!()
-    }
-
     let pathnames = if arguments.is_empty() {
-        vec![".".to_string()]
+        vec![OsString::from(".")]
     } else {
         arguments.into()
     };
+    let predicate = Predicate::from_options(&options);
+    let mut output = StructuredWriter::new(stdout(), &options);
     let mut status = 0;
     for pathname in pathnames {
-        match print_matches(&pathname, &options) {
+        match print_matches(&pathname, &options, &predicate, &mut output) {
             Ok(print_status) => status += print_status,
             Err(error) => {
-                eprintln!("{pathname}: {error}");
+                eprintln!("{}: {error}", pathname.to_string_lossy());
                 status += 1;
             }
         }
     }
+    output.finish()?;
     Ok(status)
 }
@@ -5,20 +5,63 @@
 
 use crate::{
     enumerated_record::EnumeratedRecord,
-    shell::{FileOpener, Options, STDIN_PATHNAME, ShellResult, parse_options},
-    util::{exit_with_result, help, run_command},
+    output::StructuredWriter,
+    parallel::{ordered_map, worker_count},
+    predicate::{Predicate, Term},
+    shell::{
+        FileOpener, Options, Record, STDIN_PATHNAME, ShellResult, UsageError, file_records,
+        parse_options,
+    },
+    util::{command_output, exit_with_result, help, print_output},
 };
+use bstr::BStr;
 use itertools::Either;
-use regex_splitter::RegexSplitter;
-use std::io::{IsTerminal, Write, stdout};
+use std::{
+    ffi::OsString,
+    io::{self, stdout},
+    process::Output,
+};
 
 pub const FILTER_HELP: &str = include_str!("filter.md");
 pub const FILTER_HELP_VERBOSE: &str = include_str!("filter_verbose.md");
 
-fn print_matches(pathname: &str, splitter: RegexSplitter, options: &Options) -> ShellResult {
-    let mut stdout = stdout();
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "CEeFhJjlmnoPpRrSvwxZ";
+
+/// Evaluates `predicate` on `er`. Returns `er`, whether it matched, and the
+/// `Output`s of the commands that were run (with the commands).
+fn evaluate<'a, 'p>(
+    er: EnumeratedRecord<'a>,
+    predicate: &'p Predicate,
+) -> (
+    EnumeratedRecord<'a>,
+    bool,
+    Vec<(&'p str, io::Result<Output>)>,
+) {
+    let mut outputs = Vec::new();
+    let matched = predicate.evaluate(&mut |term| match term {
+        Term::Match(re) => re.is_match(&er.r),
+        Term::Prune(re) => !re.is_match(&er.r),
+        // `filter_main` rejects these.
+        Term::Mtime(_) => false,
+        Term::Command(command) => {
+            let output = command_output(command, &[&er.r]);
+            let succeeded = matches!(&output, Ok(output) if output.status.code().unwrap_or(0) == 0);
+            outputs.push((command.as_str(), output));
+            succeeded
+        }
+    });
+    (er, matched, outputs)
+}
+
+fn print_matches<'a>(
+    pathname: &'a BStr,
+    records: impl Iterator<Item = Record<'a>>,
+    options: &Options,
+    predicate: &Predicate,
+    output: &mut StructuredWriter,
+) -> ShellResult {
     let mut matched = false;
-    let records = splitter.map_while(Result::ok);
     let records = match options.limit {
         Some(limit) => Either::Right(if limit > 0 {
             Either::Right(records.take(limit as usize))
@@ -27,70 +70,48 @@ fn print_matches(pathname: &str, splitter: RegexSplitter, options: &Options) ->
         }),
         None => Either::Left(records),
     };
-    for er in records
-        .enumerate()
-        .map(|pair| EnumeratedRecord {
-            n: if options.no_enumerate {
-                None
-            } else {
-                Some(pair.0)
-            },
-            pathname,
-            r: pair.1,
-        })
-        .filter(|er| {
-            for re in &options.prune_expressions {
-                if re.is_match(&er.r) {
-                    return false;
-                }
-                matched = true;
-                if options.limit == Some(0) {
-                    return false;
-                }
+    let candidates = records.enumerate().map(|(n, record)| EnumeratedRecord {
+        n: if options.no_enumerate { None } else { Some(n) },
+        pathname,
+        r: record.r,
+        fields: record.fields,
+    });
+
+    // The `-x` commands are the expensive part, so they are what we run in
+    // parallel.
+    let mut result = Ok(());
+    ordered_map(
+        candidates,
+        worker_count(options),
+        |er| evaluate(er, predicate),
+        |(er, passed, outputs)| {
+            if result.is_err() {
+                return;
             }
-            for re in &options.match_expressions {
-                if !re.is_match(&er.r) {
-                    return false;
-                }
-                matched = true;
-                if options.limit == Some(0) {
-                    return false;
+            for (command, output) in outputs {
+                let run_status = match output {
+                    Ok(output) => print_output(&output, options.verbose),
+                    Err(error) => Err(error.into()),
+                };
+                if let Err(error) = run_status {
+                    eprintln!("{command} \"{}\": {error}", String::from_utf8_lossy(&er.r));
                 }
             }
-            for command in &options.match_commands {
-                match run_command(command, &[&er.r], options.verbose) {
-                    Ok(status) => {
-                        if status != 0 {
-                            return false;
-                        }
-                        matched = true;
-                        if options.limit == Some(0) {
-                            return false;
-                        }
-                    }
-                    Err(error) => {
-                        eprintln!("{command} \"{}\": {error}", String::from_utf8_lossy(&er.r));
-                        return false;
-                    }
+            if passed {
+                matched = true;
+                if options.limit != Some(0) && (options.print_empty || !er.r.is_empty()) {
+                    result = output.write(&er);
                 }
             }
-            true
-        })
-    {
-        if options.json_output {
-            let t = stdout.is_terminal();
-            er.write_json(&mut stdout, t, options)?;
-            stdout.write_all(b",\n")?;
-        } else {
-            er.write_columns(&mut stdout, options)?;
-        }
-    }
+        },
+    );
+    result?;
     Ok(i32::from(!matched))
 }
 
 /// Runs the `filter` command on `arguments`.
-pub fn filter_main(arguments: &[String]) -> ShellResult {
-    let (options, arguments) = parse_options(arguments)?;
+pub fn filter_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
     if options.help {
         exit_with_result(help(
             0,
@@ -103,21 +124,19 @@ pub fn filter_main(arguments: &[String]) -> ShellResult {
             },
         ));
     }
-    if options.json_input || options.json_output {
-        unimplemented!()
+    let predicate = Predicate::from_options(&options);
+    if predicate.any_term(&|term| matches!(term, Term::Mtime(_))) {
+        return Err(UsageError::new("filter cannot test modification times (M:)").into());
     }
-
+    let mut output = StructuredWriter::new(stdout(), &options);
     let mut status = 0;
-    for file in FileOpener::new(arguments) {
+    for file in FileOpener::new(arguments, &options).mapped() {
         let pathname = file.pathname.unwrap_or(&STDIN_PATHNAME);
         match file.read {
             Ok(mut read) => {
-                let s = print_matches(
-                    pathname,
-                    RegexSplitter::new(&mut read, &options.input_record_delimiter),
-                    &options,
-                )?;
-                if s != 0 {
+                let mut records = file_records(file.map.as_deref(), &mut read, pathname, &options);
+                let s = print_matches(pathname, &mut records, &options, &predicate, &mut output)?;
+                if s != 0 || records.errors != 0 {
                     status += 1;
                 }
             }
@@ -127,5 +146,6 @@ pub fn filter_main(arguments: &[String]) -> ShellResult {
             }
         }
     }
+    output.finish()?;
     Ok(status)
 }
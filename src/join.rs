@@ -0,0 +1,318 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `futils join` command.
+
+use crate::{
+    fields::{parse_fields, select_fields},
+    output::{StructuredWrite, StructuredWriter},
+    shell::{Options, ShellResult, UsageError, open_pair, parse_options, read_records},
+    util::{StrOrBytes, exit_with_result, help, icmp},
+};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    ffi::OsString,
+    io::{Error, Write, stdout},
+    iter::{Peekable, repeat_n},
+};
+
+pub const JOIN_HELP: &str = include_str!("join.md");
+pub const JOIN_HELP_VERBOSE: &str = include_str!("join_verbose.md");
+
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "cFfhJjkoRrSvZ";
+
+/// Which unmatched records to print, in addition to the matched ones.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Inner,
+    Left,
+    Right,
+    Full,
+}
+
+impl Kind {
+    fn new(kind: Option<&str>) -> Result<Self, UsageError> {
+        match kind {
+            None | Some("inner") => Ok(Self::Inner),
+            Some("left") => Ok(Self::Left),
+            Some("right") => Ok(Self::Right),
+            Some("full") => Ok(Self::Full),
+            Some(_) => Err(UsageError::new(
+                "join kind must be 1 of inner, left, right, or full",
+            )),
+        }
+    }
+
+    fn keeps_left(self) -> bool {
+        self == Self::Left || self == Self::Full
+    }
+
+    fn keeps_right(self) -> bool {
+        self == Self::Right || self == Self::Full
+    }
+}
+
+/// The input records from 1 side of the join, along with which field is the
+/// key.
+struct Side<I: Iterator<Item = Vec<u8>>> {
+    records: Peekable<I>,
+    key: isize,
+    /// The number of non-key fields in the first record, so that we can print
+    /// the same number of empty fields whenever this side has no match.
+    width: usize,
+}
+
+/// Returns the `key` field of `record`, or the empty string if there is no
+/// such field.
+fn key<'a>(record: &'a [u8], key: isize, options: &Options) -> &'a [u8] {
+    let fields = options
+        .input_field_delimiter
+        .split(record)
+        .collect::<Vec<&[u8]>>();
+    select_fields(&fields, &[key], false)
+        .first()
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Returns all fields of `record` except the `key` field.
+fn others<'a>(record: &'a [u8], key: isize, options: &Options) -> Vec<&'a [u8]> {
+    let fields = options
+        .input_field_delimiter
+        .split(record)
+        .collect::<Vec<&[u8]>>();
+    select_fields(&fields, &[key], true)
+}
+
+fn compare(a: &[u8], b: &[u8], options: &Options) -> Ordering {
+    if options.case_sensitive {
+        a.cmp(b)
+    } else {
+        icmp(a, b)
+    }
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Side<I> {
+    fn new(records: I, key: isize, options: &Options) -> Self {
+        let mut records = records.peekable();
+        let width = records
+            .peek()
+            .map_or(0, |first| others(first, key, options).len());
+        Self {
+            records,
+            key,
+            width,
+        }
+    }
+
+    /// Returns the next run of records that all have the same key. The run is
+    /// empty when the input is exhausted.
+    fn next_group(&mut self, options: &Options) -> Vec<Vec<u8>> {
+        let Some(first) = self.records.next() else {
+            return Vec::new();
+        };
+        let mut group = vec![first];
+        while let Some(record) = self.records.next_if(|r| {
+            compare(
+                key(r, self.key, options),
+                key(&group[0], self.key, options),
+                options,
+            ) == Ordering::Equal
+        }) {
+            group.push(record);
+        }
+        group
+    }
+}
+
+#[derive(Serialize)]
+struct Joined<'a> {
+    key: StrOrBytes<'a>,
+    left: Option<Vec<StrOrBytes<'a>>>,
+    right: Option<Vec<StrOrBytes<'a>>>,
+    #[serde(skip)]
+    left_width: usize,
+    #[serde(skip)]
+    right_width: usize,
+}
+
+impl<'a> Joined<'a> {
+    fn new(
+        key: &'a [u8],
+        left: Option<Vec<&'a [u8]>>,
+        right: Option<Vec<&'a [u8]>>,
+        left_width: usize,
+        right_width: usize,
+    ) -> Self {
+        let wrap = |fields: Vec<&'a [u8]>| -> Vec<StrOrBytes<'a>> {
+            fields.into_iter().map(StrOrBytes).collect()
+        };
+        Joined {
+            key: StrOrBytes(key),
+            left: left.map(wrap),
+            right: right.map(wrap),
+            left_width,
+            right_width,
+        }
+    }
+}
+
+impl StructuredWrite for Joined<'_> {
+    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> Result<(), Error> {
+        output.write_all(self.key.0)?;
+        for (side, width) in [
+            (&self.left, self.left_width),
+            (&self.right, self.right_width),
+        ] {
+            match side {
+                Some(fields) => {
+                    for f in fields {
+                        output.write_all(&options.output_field_delimiter)?;
+                        output.write_all(f.0)?;
+                    }
+                }
+                None => {
+                    for d in repeat_n(&options.output_field_delimiter, width) {
+                        output.write_all(d)?;
+                    }
+                }
+            }
+        }
+        output.write_all(&options.output_record_delimiter)
+    }
+
+    fn to_fields(&self, _: &Options) -> Vec<Cow<'_, [u8]>> {
+        let mut fields = vec![Cow::Borrowed(self.key.0)];
+        for (side, width) in [
+            (&self.left, self.left_width),
+            (&self.right, self.right_width),
+        ] {
+            match side {
+                Some(side) => fields.extend(side.iter().map(|f| Cow::Borrowed(f.0))),
+                None => fields.extend(repeat_n(Cow::Borrowed(b"".as_slice()), width)),
+            }
+        }
+        fields
+    }
+}
+
+/// Merges the sorted `left` and `right` inputs, printing the records whose
+/// keys match (and the unmatched records that `kind` asks for).
+fn join<L, R>(
+    mut left: Side<L>,
+    mut right: Side<R>,
+    kind: Kind,
+    options: &Options,
+    output: &mut StructuredWriter,
+) -> Result<(), Error>
+where
+    L: Iterator<Item = Vec<u8>>,
+    R: Iterator<Item = Vec<u8>>,
+{
+    let mut group1 = left.next_group(options);
+    let mut group2 = right.next_group(options);
+    loop {
+        let ordering = match (group1.first(), group2.first()) {
+            (Some(r1), Some(r2)) => compare(
+                key(r1, left.key, options),
+                key(r2, right.key, options),
+                options,
+            ),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+        match ordering {
+            Ordering::Equal => {
+                for r1 in &group1 {
+                    for r2 in &group2 {
+                        output.write(&Joined::new(
+                            key(r1, left.key, options),
+                            Some(others(r1, left.key, options)),
+                            Some(others(r2, right.key, options)),
+                            left.width,
+                            right.width,
+                        ))?;
+                    }
+                }
+                group1 = left.next_group(options);
+                group2 = right.next_group(options);
+            }
+            Ordering::Less => {
+                if kind.keeps_left() {
+                    for r1 in &group1 {
+                        output.write(&Joined::new(
+                            key(r1, left.key, options),
+                            Some(others(r1, left.key, options)),
+                            None,
+                            left.width,
+                            right.width,
+                        ))?;
+                    }
+                }
+                group1 = left.next_group(options);
+            }
+            Ordering::Greater => {
+                if kind.keeps_right() {
+                    for r2 in &group2 {
+                        output.write(&Joined::new(
+                            key(r2, right.key, options),
+                            None,
+                            Some(others(r2, right.key, options)),
+                            left.width,
+                            right.width,
+                        ))?;
+                    }
+                }
+                group2 = right.next_group(options);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs the `join` command on `arguments`.
+pub fn join_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
+    if options.help {
+        exit_with_result(help(
+            0,
+            JOIN_HELP,
+            true,
+            if options.verbose {
+                Some(JOIN_HELP_VERBOSE)
+            } else {
+                None
+            },
+        ));
+    }
+    if arguments.is_empty() || arguments.len() > 2 {
+        exit_with_result(help(-1, JOIN_HELP, false, None));
+    }
+
+    let kind = Kind::new(options.kind.as_deref())?;
+    let (key1, key2) = match parse_fields(&options.fields)?.as_slice() {
+        [] => (0, 0),
+        [key] => (*key, *key),
+        [key1, key2] => (*key1, *key2),
+        _ => return Err(UsageError::new("join takes at most 2 key fields").into()),
+    };
+
+    let [(pathname1, mut read1), (pathname2, mut read2)] = open_pair(arguments, &options)?;
+    let records1 = read_records(&mut read1, pathname1, &options);
+    let records2 = read_records(&mut read2, pathname2, &options);
+    let mut output = StructuredWriter::new(stdout(), &options);
+
+    join(
+        Side::new(records1, key1, &options),
+        Side::new(records2, key2, &options),
+        kind,
+        &options,
+        &mut output,
+    )?;
+    output.finish()?;
+    Ok(0)
+}
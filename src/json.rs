@@ -0,0 +1,180 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reading records from streams of JSON values.
+
+use serde_json::{Deserializer, Map, StreamDeserializer, Value, de::IoRead};
+use std::{collections::VecDeque, io::Read};
+
+/// A record parsed from JSON input.
+pub enum JsonRecord {
+    /// A whole record that has not been split into fields, e.g. the `r` member
+    /// of the objects that `records -J` emits, or a scalar value.
+    Record(Vec<u8>),
+
+    /// A record that was already split into fields, e.g. the `fields` member
+    /// of the objects that `fields -J` emits, an array, or the values of an
+    /// arbitrary object.
+    Fields(Vec<Vec<u8>>),
+}
+
+/// Returns `value` as text: strings as themselves, `null` as the empty string,
+/// and anything else as its compact JSON representation.
+fn text(value: Value) -> Vec<u8> {
+    match value {
+        Value::String(s) => s.into_bytes(),
+        Value::Null => Vec::new(),
+        value => value.to_string().into_bytes(),
+    }
+}
+
+/// Like `text`, but also decodes arrays of byte values, which is how
+/// `serialize_str_or_bytes` (and `serde` generally) represents byte strings
+/// that are not valid UTF-8.
+fn bytes(value: Value) -> Vec<u8> {
+    if let Value::Array(values) = &value {
+        let decoded: Option<Vec<u8>> = values
+            .iter()
+            .map(|v| v.as_u64().and_then(|n| u8::try_from(n).ok()))
+            .collect();
+        if let Some(decoded) = decoded {
+            return decoded;
+        }
+    }
+    text(value)
+}
+
+fn object_record(mut object: Map<String, Value>) -> JsonRecord {
+    if let Some(fields) = object.remove("fields") {
+        return match fields {
+            Value::Array(fields) => JsonRecord::Fields(fields.into_iter().map(bytes).collect()),
+            Value::Object(fields) => {
+                JsonRecord::Fields(fields.into_iter().map(|(_, v)| bytes(v)).collect())
+            }
+            fields => JsonRecord::Fields(vec![bytes(fields)]),
+        };
+    }
+    if let Some(r) = object.remove("r") {
+        return JsonRecord::Record(bytes(r));
+    }
+    JsonRecord::Fields(object.into_iter().map(|(_, v)| text(v)).collect())
+}
+
+/// An `Iterator` over the records in a stream of JSON values. The stream can
+/// be a single top-level array of records, a sequence of whitespace-separated
+/// values (such as NDJSON), or a mix of the two.
+///
+/// Each object is a record. If the object has a `fields` member (as `fields -J`
+/// emits), its elements (or, if it is an object, its values) are the record’s
+/// fields; if it has an `r` member (as
+/// `records -J` emits), that is the record; otherwise, the object’s values (in
+/// the order they appear) are the record’s fields. Arrays nested in a top-level array are
+/// records whose elements are fields, and scalars are records by themselves.
+pub struct JsonRecords<'a> {
+    values: StreamDeserializer<'a, IoRead<&'a mut dyn Read>, Value>,
+    pending: VecDeque<Value>,
+}
+
+impl<'a> JsonRecords<'a> {
+    pub fn new(reader: &'a mut dyn Read) -> Self {
+        JsonRecords {
+            values: Deserializer::from_reader(reader).into_iter::<Value>(),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl Iterator for JsonRecords<'_> {
+    type Item = Result<JsonRecord, serde_json::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let value = match self.pending.pop_front() {
+                Some(value) => value,
+                None => match self.values.next()? {
+                    Ok(Value::Array(values)) => {
+                        self.pending.extend(values);
+                        continue;
+                    }
+                    Ok(value) => value,
+                    Err(error) => return Some(Err(error)),
+                },
+            };
+            let record = match value {
+                Value::Object(object) => object_record(object),
+                Value::Array(fields) => JsonRecord::Fields(fields.into_iter().map(bytes).collect()),
+                value => JsonRecord::Record(text(value)),
+            };
+            return Some(Ok(record));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json::{JsonRecord, JsonRecords};
+
+    fn records(input: &str) -> Vec<Vec<u8>> {
+        let mut input = input.as_bytes();
+        JsonRecords::new(&mut input)
+            .map(|r| match r.unwrap() {
+                JsonRecord::Record(r) => r,
+                JsonRecord::Fields(fields) => fields.join(b"\t".as_slice()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_json_records_shapes() {
+        let expected: Vec<Vec<u8>> = vec![b"goat".to_vec(), b"sheep".to_vec()];
+        assert_eq!(expected, records(r#"[{"n":0,"r":"goat"},{"r":"sheep"}]"#));
+        assert_eq!(expected, records("{\"r\":\"goat\"}\n{\"r\":\"sheep\"}\n"));
+        assert_eq!(expected, records(r#""goat" "sheep""#));
+
+        let expected: Vec<Vec<u8>> = vec![b"1\tgoat".to_vec(), b"12\tsheep".to_vec()];
+        assert_eq!(expected, records(r#"[["1","goat"],["12","sheep"]]"#));
+        assert_eq!(
+            expected,
+            records(r#"{"fields":["1",[103,111,97,116]]} {"fields":["12","sheep"]}"#)
+        );
+        assert_eq!(
+            expected,
+            records(r#"{"count":1,"name":"goat"} {"count":12,"name":"sheep"}"#)
+        );
+        assert_eq!(
+            expected,
+            records(
+                r#"{"fields":{"Count":"1","Name":"goat"}} {"fields":{"Count":"12","Name":"sheep"}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_records_order() {
+        let expected: Vec<Vec<u8>> = vec![b"goat\t1".to_vec(), b"sheep\t12".to_vec()];
+        assert_eq!(
+            expected,
+            records(r#"{"name":"goat","count":1} {"name":"sheep","count":12}"#)
+        );
+        assert_eq!(
+            expected,
+            records(
+                r#"{"fields":{"Name":"goat","Count":"1"}} {"fields":{"Name":"sheep","Count":"12"}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_records_empty_object() {
+        let expected: Vec<Vec<u8>> = vec![b"goat".to_vec(), Vec::new(), b"sheep".to_vec()];
+        assert_eq!(expected, records("\"goat\"\n{}\n\"sheep\"\n"));
+    }
+
+    #[test]
+    fn test_json_records_error() {
+        let mut input = b"{\"r\":\"goat\"} {\"r\":".as_slice();
+        let mut records = JsonRecords::new(&mut input);
+        assert!(matches!(records.next(), Some(Ok(JsonRecord::Record(_)))));
+        assert!(matches!(records.next(), Some(Err(_))));
+    }
+}
@@ -1,7 +1,15 @@
 // Copyright 2023 by [Chris Palmer](https://noncombatant.org)
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::util::serialize_str_or_bytes;
+use bstr::BStr;
 use serde::Serialize;
+use std::{
+    ffi::{CString, OsString},
+    fs, io,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
 
 pub type LinkCount = u64;
 pub type DeviceNumber = u64;
@@ -10,7 +18,8 @@ pub type BlockSize = i64;
 
 #[derive(Serialize)]
 pub struct Status<'a> {
-    pub name: &'a str,
+    #[serde(serialize_with = "serialize_str_or_bytes")]
+    pub name: &'a BStr,
     pub file_type: String,
     pub size: i64,
     pub modified_time: String,
@@ -26,3 +35,66 @@ pub struct Status<'a> {
     pub blocks: i64,
     pub block_size: BlockSize,
 }
+
+/// Renames `from` to `to`, failing with `EEXIST` rather than replacing `to` if
+/// it exists. Fails with `EINVAL` if the filesystem does not support that.
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    let from = CString::new(from.as_os_str().as_bytes())?;
+    let to = CString::new(to.as_os_str().as_bytes())?;
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Returns the mount points of all mounted filesystems.
+pub fn mount_points() -> io::Result<Vec<PathBuf>> {
+    let mounts = fs::read("/proc/self/mounts")?;
+    Ok(mounts
+        .split(|&b| b == b'\n')
+        .filter_map(|line| line.split(|&b| b == b' ').nth(1))
+        .map(|m| PathBuf::from(OsString::from_vec(unescape_mount_point(m))))
+        .collect())
+}
+
+/// Decodes the octal escapes (such as `\040` for a space) in a mount point in
+/// `/proc/self/mounts`.
+fn unescape_mount_point(escaped: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(escaped.len());
+    let mut i = 0;
+    while i < escaped.len() {
+        if escaped[i] == b'\\'
+            && let Some(b) = escaped
+                .get(i + 1..i + 4)
+                .and_then(|octal| std::str::from_utf8(octal).ok())
+                .and_then(|octal| u8::from_str_radix(octal, 8).ok())
+        {
+            unescaped.push(b);
+            i += 4;
+        } else {
+            unescaped.push(escaped[i]);
+            i += 1;
+        }
+    }
+    unescaped
+}
+
+#[test]
+fn test_unescape_mount_point() {
+    assert_eq!(b"/".as_slice(), unescape_mount_point(b"/"));
+    assert_eq!(
+        b"/media/my goat\\t".as_slice(),
+        unescape_mount_point(b"/media/my\\040goat\\134t")
+    );
+    assert_eq!(b"/bad\\9".as_slice(), unescape_mount_point(b"/bad\\9"));
+}
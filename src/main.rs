@@ -18,19 +18,36 @@
 )]
 #![deny(warnings)]
 
+mod apply;
 mod common;
+mod config;
+mod csv_records;
+mod decompress;
 mod enumerated_record;
 mod fields;
 mod fileid;
 mod files;
 mod filter;
+mod join;
+mod json;
 mod map;
 mod mapx;
 mod markdown;
+mod output;
+mod parallel;
+mod predicate;
+mod range;
 mod records;
+mod reduce;
+mod regex_splitter;
 mod shell;
+mod sort;
 mod status;
+mod table;
 mod time;
+mod trash;
+mod tutorial;
+mod uniq;
 mod util;
 mod version;
 
@@ -38,17 +55,24 @@ mod version;
 #[cfg_attr(target_os = "macos", path = "darwin.rs")]
 mod os;
 
+use apply::{APPLY_HELP, apply_main};
 use common::{COMMON_HELP, common_main};
 use fields::{FIELDS_HELP, fields_main};
 use fileid::{FILEID_HELP, fileid_main};
 use files::{FILES_HELP, files_main};
 use filter::{FILTER_HELP, filter_main};
+use join::{JOIN_HELP, join_main};
 use map::{MAP_HELP, map_main};
 use mapx::{MAPX_HELP, mapx_main};
 use markdown::{MARKDOWN_HELP, markdown_main};
 use records::{RECORDS_HELP, records_main};
+use reduce::{REDUCE_HELP, reduce_main};
+use sort::{SORT_HELP, sort_main};
 use status::{STATUS_HELP, status_main};
-use std::{env, process::exit};
+use std::{env, ffi::OsString, process::exit};
+use trash::{TRASH_HELP, trash_main};
+use tutorial::{TUTORIAL_HELP, tutorial_main};
+use uniq::{UNIQ_HELP, count_main, uniq_main};
 use util::{exit_with_result, file_name, help};
 use version::{VERSION_HELP, version_main};
 
@@ -65,49 +89,39 @@ fn reset_sigpipe() {
 fn main() {
     reset_sigpipe();
 
-    // TODO: Make `arguments` be `Vec<OsString>`, and propagate the API change
-    // throughout (!). This probably means we can't use `getopt`, which seems to
-    // want only `String`s. Perhaps `clap` is the way to go. Until then, the
-    // least we can do is warn people:
-    let args_os = env::args_os();
-    let mut arguments: Vec<String> = Vec::with_capacity(args_os.size_hint().0);
-    for arg in args_os {
-        match arg.to_str() {
-            Some(string) => arguments.push(String::from(string)),
-            None => eprintln!(
-                "Could not convert \"{}\" to UTF-8. Skipping.",
-                arg.to_string_lossy()
-            ),
-        }
-    }
+    let mut arguments: Vec<OsString> = env::args_os().collect();
 
     // If we were invoked as `futils`, shift the arguments left.
-    let program_name = arguments[0].clone();
-    let mut program_name = file_name(&program_name).expect("could not get program name");
-    if program_name.eq("futils") {
+    if file_name(&arguments[0]) == Some("futils") {
         arguments.remove(0);
     }
     if arguments.is_empty() {
         exit_with_result(help(-1, MAIN_HELP, false, None));
-    } else {
-        program_name = file_name(&arguments[0]).expect("could not get program name");
     }
+    let program_name = file_name(&arguments[0]).unwrap_or_default();
 
     if program_name == "help" || program_name == "-h" || program_name == "--help" {
         exit_with_result(if arguments.len() < 2 {
             help(0, MAIN_HELP, false, None)
         } else {
-            match arguments[1].as_str() {
+            match arguments[1].to_str().unwrap_or_default() {
+                "apply" => help(0, APPLY_HELP, true, None),
                 "common" => help(0, COMMON_HELP, true, None),
                 "fields" => help(0, FIELDS_HELP, true, None),
                 "fileid" => help(0, FILEID_HELP, true, None),
                 "files" => help(0, FILES_HELP, true, None),
                 "filter" => help(0, FILTER_HELP, true, None),
+                "join" => help(0, JOIN_HELP, true, None),
                 "map" => help(0, MAP_HELP, true, None),
                 "mapx" => help(0, MAPX_HELP, true, None),
                 "markdown" => help(0, MARKDOWN_HELP, true, None),
                 "records" => help(0, RECORDS_HELP, true, None),
+                "reduce" => help(0, REDUCE_HELP, true, None),
+                "sort" => help(0, SORT_HELP, true, None),
                 "status" => help(0, STATUS_HELP, true, None),
+                "trash" => help(0, TRASH_HELP, true, None),
+                "tutorial" => help(0, TUTORIAL_HELP, false, None),
+                "uniq" | "count" => help(0, UNIQ_HELP, true, None),
                 "version" => help(0, VERSION_HELP, true, None),
                 &_ => help(-1, MAIN_HELP, false, None),
             }
@@ -115,16 +129,24 @@ fn main() {
     }
 
     match match program_name {
+        "apply" => apply_main(&arguments),
         "common" => common_main(&arguments),
+        "count" => count_main(&arguments),
         "fields" => fields_main(&arguments),
         "fileid" => fileid_main(&arguments),
         "files" => files_main(&arguments),
         "filter" => filter_main(&arguments),
+        "join" => join_main(&arguments),
         "map" => map_main(&arguments),
         "mapx" => mapx_main(&arguments),
         "markdown" => markdown_main(&arguments),
         "records" => records_main(&arguments),
+        "reduce" => reduce_main(&arguments),
+        "sort" => sort_main(&arguments),
         "status" => status_main(&arguments),
+        "trash" => trash_main(&arguments),
+        "tutorial" => tutorial_main(&arguments),
+        "uniq" => uniq_main(&arguments),
         "version" => version_main(&arguments),
         _ => help(-1, MAIN_HELP, false, None),
     } {
@@ -4,46 +4,65 @@
 //! The `futils map` command.
 
 use crate::{
-    shell::{FileOpener, Options, STDIN_PATHNAME, ShellResult, parse_options},
-    util::{exit_with_result, help, run_command},
+    parallel::{ordered_map, worker_count},
+    shell::{FileOpener, Options, STDIN_PATHNAME, ShellResult, parse_options, read_records},
+    util::{command_output, exit_with_result, help, print_output},
 };
 use itertools::Itertools;
-use regex_splitter::RegexSplitter;
+use std::{ffi::OsString, io, iter::zip, process::Output};
 
 pub const MAP_HELP: &str = include_str!("map.md");
 pub const MAP_HELP_VERBOSE: &str = include_str!("map_verbose.md");
 
-/// Iterates over `RegexSplitter` and runs each of the `commands` on each
-/// record.
-fn map(splitter: RegexSplitter, options: &Options) -> i32 {
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "FhjlPrSvwxZ";
+
+/// Iterates over `records` and runs each of the `commands` on each record.
+/// With `-P`, runs the commands on several chunks of records at once, but
+/// still prints their output in input order.
+fn map(records: impl Iterator<Item = Vec<u8>>, options: &Options) -> i32 {
     let mut status = 0;
     let chunk_size = options
         .limit
         .map_or(1, |limit| if limit > 0 { limit as usize } else { 1 });
-    for chunk in &splitter.map_while(Result::ok).chunks(chunk_size) {
-        // TODO: This is ugly and allocates.
-        let records: Vec<Vec<u8>> = chunk.collect();
-        let records: Vec<&[u8]> = records.iter().map(std::vec::Vec::as_slice).collect();
-        for command in &options.match_commands {
-            match run_command(command, &records, true) {
-                Ok(run_status) => {
-                    if run_status != 0 {
+    let chunks = records.chunks(chunk_size);
+    ordered_map(
+        chunks.into_iter().map(Iterator::collect::<Vec<Vec<u8>>>),
+        worker_count(options),
+        |records| {
+            let records: Vec<&[u8]> = records.iter().map(Vec::as_slice).collect();
+            options
+                .match_commands
+                .iter()
+                .map(|command| command_output(command, &records))
+                .collect::<Vec<io::Result<Output>>>()
+        },
+        |outputs| {
+            for (command, output) in zip(&options.match_commands, outputs) {
+                let run_status = match output {
+                    Ok(output) => print_output(&output, true),
+                    Err(error) => Err(error.into()),
+                };
+                match run_status {
+                    Ok(run_status) => {
+                        if run_status != 0 {
+                            status += 1;
+                        }
+                    }
+                    Err(error) => {
+                        eprintln!("{command} ... : {error}");
                         status += 1;
                     }
                 }
-                Err(error) => {
-                    eprintln!("{command} ... : {error}");
-                    status += 1;
-                }
             }
-        }
-    }
+        },
+    );
     status
 }
 
 /// Runs the `map` command on `arguments`.
-pub fn map_main(arguments: &[String]) -> ShellResult {
-    let (options, arguments) = parse_options(arguments)?;
+pub fn map_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
     if options.help {
         exit_with_result(help(
             0,
@@ -56,19 +75,12 @@ pub fn map_main(arguments: &[String]) -> ShellResult {
             },
         ));
     }
-    if options.json_input || options.json_output {
-        unimplemented!()
-    }
-
     let mut status = 0;
-    for file in FileOpener::new(arguments) {
+    for file in FileOpener::new(arguments, &options) {
         let pathname = file.pathname.unwrap_or(&STDIN_PATHNAME);
         match file.read {
             Ok(mut read) => {
-                status += map(
-                    RegexSplitter::new(&mut read, &options.input_record_delimiter),
-                    &options,
-                );
+                status += map(read_records(&mut read, pathname, &options), &options);
             }
             Err(error) => {
                 eprintln!("{pathname}: {error}");
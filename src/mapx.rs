@@ -4,48 +4,71 @@
 //! The `futils mapx` command.
 
 use crate::{
-    shell::{Options, ShellResult, parse_options},
-    util::{exit_with_result, help, run_command},
+    parallel::{ordered_map, worker_count},
+    shell::{Options, STDIN_PATHNAME, ShellResult, parse_options, read_records},
+    util::{command_output, exit_with_result, help, print_output},
 };
 use itertools::{Itertools, chain};
-use regex_splitter::RegexSplitter;
-use std::io::stdin;
+use std::{
+    ffi::OsString,
+    io::{self, stdin},
+    os::unix::ffi::OsStrExt,
+};
 
 pub const MAPX_HELP: &str = include_str!("mapx.md");
 pub const MAPX_HELP_VERBOSE: &str = include_str!("mapx_verbose.md");
 
-/// Iterates over `RegexSplitter` and runs each of the `commands` on each
-/// record.
-fn mapx(splitter: RegexSplitter, options: &Options, command: &[String]) -> i32 {
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "FhjlPrSvw";
+
+/// Iterates over `records` and runs `command` on each record. With `-P`, runs
+/// `command` on several chunks of records at once, but still prints the
+/// output in input order.
+fn mapx(records: impl Iterator<Item = Vec<u8>>, options: &Options, command: &[OsString]) -> i32 {
     let mut status = 0;
     let chunk_size = options
         .limit
         .map_or(1, |limit| if limit > 0 { limit as usize } else { 1 });
-    for chunk in &splitter.map_while(Result::ok).chunks(chunk_size) {
-        // TODO: This is ugly and allocates.
-        let records: Vec<Vec<u8>> = chunk.collect();
-        let arguments = chain(
-            command.iter().skip(1).map(std::string::String::as_bytes),
-            records.iter().map(std::vec::Vec::as_slice),
-        );
-        match run_command(&command[0], &arguments.collect::<Vec<&[u8]>>(), true) {
-            Ok(run_status) => {
-                if run_status != 0 {
+    let chunks = records.chunks(chunk_size);
+    ordered_map(
+        chunks.into_iter().map(Iterator::collect::<Vec<Vec<u8>>>),
+        worker_count(options),
+        |records| {
+            let arguments = chain(
+                command.iter().skip(1).map(|a| a.as_bytes()),
+                records.iter().map(std::vec::Vec::as_slice),
+            );
+            command[0]
+                .to_str()
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "command not valid UTF-8")
+                })
+                .and_then(|c| command_output(c, &arguments.collect::<Vec<&[u8]>>()))
+        },
+        |output| {
+            let run_status = match output {
+                Ok(output) => print_output(&output, true),
+                Err(error) => Err(error.into()),
+            };
+            match run_status {
+                Ok(run_status) => {
+                    if run_status != 0 {
+                        status += 1;
+                    }
+                }
+                Err(error) => {
+                    eprintln!("{command:#?} ... : {error}");
                     status += 1;
                 }
             }
-            Err(error) => {
-                eprintln!("{command:#?} ... : {error}");
-                status += 1;
-            }
-        }
-    }
+        },
+    );
     status
 }
 
 /// Runs the `mapx` command on `arguments`.
-pub fn mapx_main(arguments: &[String]) -> ShellResult {
-    let (options, arguments) = parse_options(arguments)?;
+pub fn mapx_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
     if options.help {
         exit_with_result(help(
             0,
@@ -58,11 +81,8 @@ pub fn mapx_main(arguments: &[String]) -> ShellResult {
             },
         ));
     }
-    if options.json_input || options.json_output {
-        unimplemented!()
-    }
     Ok(mapx(
-        RegexSplitter::new(&mut stdin(), &options.input_record_delimiter),
+        read_records(&mut stdin(), &STDIN_PATHNAME, &options),
         &options,
         arguments,
     ))
@@ -5,19 +5,23 @@ use crate::{
     shell::{FileOpener, STDIN_PATHNAME, ShellResult, parse_options},
     util::{exit_with_result, help, skin, terminal_text},
 };
+use std::ffi::OsString;
 
 pub const MARKDOWN_HELP: &str = include_str!("markdown.md");
 
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "hZ";
+
 /// Runs the `markdown` command on `arguments`.
-pub fn markdown_main(arguments: &[String]) -> ShellResult {
-    let (options, arguments) = parse_options(arguments)?;
+pub fn markdown_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
     if options.help {
         exit_with_result(help(0, MARKDOWN_HELP, false, None));
     }
 
     let skin = skin();
     let mut status = 0;
-    for file in FileOpener::new(arguments) {
+    for file in FileOpener::new(arguments, &options) {
         match file.read {
             Ok(mut read) => {
                 let mut buffer = String::new();
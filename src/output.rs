@@ -0,0 +1,382 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! The output layer. Programs that print structured data (records, file
+//! statuses, and so on) print it through a `StructuredWriter`, which formats
+//! it according to the `-o` option.
+
+use crate::{shell::Options, table::Table, util::text_width};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    io::{self, Write},
+    str::FromStr,
+};
+
+/// The output formats that `-o` selects.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// Fields delimited by `-F`, and records delimited by `-R`.
+    Columns,
+    /// Fields delimited by commas, quoted as in RFC 4180 where necessary, and
+    /// records delimited by `-R`.
+    Csv,
+    /// A single JSON array, with 1 record per line.
+    Json,
+    /// 1 JSON value per line (NDJSON).
+    Ndjson,
+    /// A single JSON array, indented for people to read.
+    Pretty,
+    /// Columns aligned for people to read, with a header row, and fitted to
+    /// the width of the terminal.
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "columns" => Ok(Self::Columns),
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            "pretty" => Ok(Self::Pretty),
+            "table" => Ok(Self::Table),
+            _ => Err(format!(
+                "invalid output format \"{s}\" (use columns, csv, json, ndjson, pretty, or table)"
+            )),
+        }
+    }
+}
+
+/// Things that `StructuredWriter` can write. The JSON formats come from
+/// `Serialize`; implementors provide the columns format, and the list of
+/// fields that the CSV and table formats use.
+pub trait StructuredWrite: Serialize {
+    /// Writes `self` to `output` as fields delimited by
+    /// `options.output_field_delimiter`, followed by
+    /// `options.output_record_delimiter`.
+    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> io::Result<()>;
+
+    /// Returns the fields of `self`, in the same order as `write_columns`
+    /// writes them, but without padding.
+    fn to_fields(&self, options: &Options) -> Vec<Cow<'_, [u8]>>;
+
+    /// Returns the names of the fields that `to_fields` returns, for the
+    /// header row of the table format. By default, there is no header row.
+    fn headers(&self, _options: &Options) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Splits a whole `record` into fields with `options.input_field_delimiter`,
+/// for implementors of `StructuredWrite::to_fields` that hold records rather
+/// than fields.
+pub fn split_record<'a>(record: &'a [u8], options: &Options) -> Vec<Cow<'a, [u8]>> {
+    options
+        .input_field_delimiter
+        .split(record)
+        .map(Cow::Borrowed)
+        .collect()
+}
+
+/// Returns the fields of a record: `fields`, if the input format delimited
+/// them (see `shell::Record`), or else the whole `record` split with
+/// `split_record`.
+pub fn record_fields<'a>(
+    record: &'a [u8],
+    fields: Option<&'a [Vec<u8>]>,
+    options: &Options,
+) -> Vec<Cow<'a, [u8]>> {
+    fields.map_or_else(
+        || split_record(record, options),
+        |fields| fields.iter().map(|f| Cow::Borrowed(f.as_slice())).collect(),
+    )
+}
+
+/// Returns the decimal representation of `n`, for implementors of
+/// `StructuredWrite::to_fields`.
+pub fn number_field(n: impl Display) -> Cow<'static, [u8]> {
+    Cow::Owned(n.to_string().into_bytes())
+}
+
+/// Writes `field` to `output`, quoting it as RFC 4180 requires if it contains
+/// a comma, a quote, or a line break. Quotes inside the field are doubled.
+fn write_csv_field(output: &mut dyn Write, field: &[u8]) -> io::Result<()> {
+    if field
+        .iter()
+        .any(|b| matches!(b, b',' | b'"' | b'\r' | b'\n'))
+    {
+        output.write_all(b"\"")?;
+        for part in field.split_inclusive(|&b| b == b'"') {
+            output.write_all(part)?;
+            if part.ends_with(b"\"") {
+                output.write_all(b"\"")?;
+            }
+        }
+        output.write_all(b"\"")
+    } else {
+        output.write_all(field)
+    }
+}
+
+/// Writes a sequence of `StructuredWrite`s to an output stream, in the format
+/// given by `options.output_format`. Call `finish` after the last one, to
+/// close the JSON array (if any) and see any error. If a program returns early
+/// with an error, dropping the writer closes the array, so that the output is
+/// still valid JSON. Nothing closes it if the program panics (release builds
+/// abort without unwinding) or calls `process::exit` while the writer is live,
+/// so programs only `exit` (e.g. via `util::exit_with_result`) before creating
+/// a writer or after returning from their `*_main` function.
+pub struct StructuredWriter<'a> {
+    output: Box<dyn Write + 'a>,
+    options: &'a Options,
+    count: usize,
+    closed: bool,
+    table: Table,
+}
+
+impl<'a> StructuredWriter<'a> {
+    pub fn new(output: impl Write + 'a, options: &'a Options) -> Self {
+        Self {
+            output: Box::new(output),
+            options,
+            count: 0,
+            closed: false,
+            table: Table::default(),
+        }
+    }
+
+    /// Returns the format this writer writes.
+    pub const fn format(&self) -> OutputFormat {
+        self.options.output_format
+    }
+
+    /// Writes raw `bytes`, such as a header row, if the format is columns.
+    /// Does nothing for the other formats.
+    pub fn write_columns_only(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.format() == OutputFormat::Columns {
+            self.output.write_all(bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `value`.
+    pub fn write(&mut self, value: &impl StructuredWrite) -> io::Result<()> {
+        let output = &mut self.output;
+        match self.options.output_format {
+            OutputFormat::Columns => value.write_columns(output, self.options)?,
+            OutputFormat::Csv => {
+                for (i, field) in value.to_fields(self.options).iter().enumerate() {
+                    if i > 0 {
+                        output.write_all(b",")?;
+                    }
+                    write_csv_field(output, field)?;
+                }
+                output.write_all(&self.options.output_record_delimiter)?;
+            }
+            OutputFormat::Table => {
+                self.table.set_headers(value.headers(self.options));
+                self.table.push(&value.to_fields(self.options));
+                if self.table.is_full() {
+                    self.table.write(
+                        output,
+                        text_width(),
+                        &self.options.output_record_delimiter,
+                    )?;
+                }
+            }
+            OutputFormat::Ndjson => {
+                serde_json::to_writer(&mut *output, value)?;
+                output.write_all(b"\n")?;
+            }
+            OutputFormat::Json | OutputFormat::Pretty => {
+                output.write_all(if self.count == 0 { b"[\n" } else { b",\n" })?;
+                if self.options.output_format == OutputFormat::Pretty {
+                    serde_json::to_writer_pretty(&mut *output, value)?;
+                } else {
+                    serde_json::to_writer(&mut *output, value)?;
+                }
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Writes any rows of the table that are buffered, and flushes. Use this
+    /// before something else writes to the same destination.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.options.output_format == OutputFormat::Table {
+            self.table.write(
+                &mut self.output,
+                text_width(),
+                &self.options.output_record_delimiter,
+            )?;
+        }
+        self.output.flush()
+    }
+
+    /// Closes the JSON array (if any), writes any rows of the table that are
+    /// still buffered, and flushes. Does nothing if already closed.
+    fn close(&mut self) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        if self.options.output_format == OutputFormat::Table {
+            self.table.write(
+                &mut self.output,
+                text_width(),
+                &self.options.output_record_delimiter,
+            )?;
+        }
+        if matches!(
+            self.options.output_format,
+            OutputFormat::Json | OutputFormat::Pretty
+        ) {
+            self.output
+                .write_all(if self.count == 0 { b"[]\n" } else { b"\n]\n" })?;
+        }
+        self.output.flush()
+    }
+
+    /// Finishes the output: closes the JSON array (if any), writes the rest of
+    /// the table (if any), and flushes.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close()
+    }
+}
+
+impl Drop for StructuredWriter<'_> {
+    fn drop(&mut self) {
+        // There is nowhere to report an error from here; `finish` is for
+        // that.
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[derive(Serialize)]
+    struct Pair {
+        a: &'static str,
+        b: u8,
+    }
+
+    impl StructuredWrite for Pair {
+        fn write_columns(&self, output: &mut dyn Write, options: &Options) -> io::Result<()> {
+            write!(output, "{}", self.a)?;
+            output.write_all(&options.output_field_delimiter)?;
+            write!(output, "{}", self.b)?;
+            output.write_all(&options.output_record_delimiter)
+        }
+
+        fn to_fields(&self, _: &Options) -> Vec<Cow<'_, [u8]>> {
+            vec![Cow::Borrowed(self.a.as_bytes()), number_field(self.b)]
+        }
+    }
+
+    /// A `Write` whose contents the test can see after the `StructuredWriter`
+    /// is done with it.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write_pairs(format: OutputFormat, pairs: &[Pair]) -> String {
+        let mut options = Options::with_defaults().unwrap();
+        options.output_format = format;
+        let shared = Shared::default();
+        let mut writer = StructuredWriter::new(shared.clone(), &options);
+        for pair in pairs {
+            writer.write(pair).unwrap();
+        }
+        writer.finish().unwrap();
+        let bytes = shared.0.borrow().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_formats() {
+        let pairs = [Pair { a: "goat", b: 1 }, Pair { a: "sheep", b: 2 }];
+        assert_eq!(
+            "goat\t1\nsheep\t2\n",
+            write_pairs(OutputFormat::Columns, &pairs)
+        );
+        assert_eq!(
+            "{\"a\":\"goat\",\"b\":1}\n{\"a\":\"sheep\",\"b\":2}\n",
+            write_pairs(OutputFormat::Ndjson, &pairs)
+        );
+        assert_eq!(
+            "[\n{\"a\":\"goat\",\"b\":1},\n{\"a\":\"sheep\",\"b\":2}\n]\n",
+            write_pairs(OutputFormat::Json, &pairs)
+        );
+        assert_eq!(
+            "[\n{\n  \"a\": \"goat\",\n  \"b\": 1\n}\n]\n",
+            write_pairs(OutputFormat::Pretty, &pairs[..1])
+        );
+        assert_eq!(
+            "goat,1\n\"goat, \"\"sheep\"\"\",2\n",
+            write_pairs(
+                OutputFormat::Csv,
+                &[
+                    Pair { a: "goat", b: 1 },
+                    Pair {
+                        a: "goat, \"sheep\"",
+                        b: 2
+                    }
+                ]
+            )
+        );
+        assert_eq!("[]\n", write_pairs(OutputFormat::Json, &[]));
+        assert_eq!("", write_pairs(OutputFormat::Ndjson, &[]));
+    }
+
+    #[test]
+    fn test_drop_closes_array() {
+        let mut options = Options::with_defaults().unwrap();
+        options.output_format = OutputFormat::Json;
+        let shared = Shared::default();
+        {
+            let mut writer = StructuredWriter::new(shared.clone(), &options);
+            writer.write(&Pair { a: "goat", b: 1 }).unwrap();
+        }
+        let bytes = shared.0.borrow().clone();
+        assert_eq!(
+            "[\n{\"a\":\"goat\",\"b\":1}\n]\n",
+            String::from_utf8(bytes).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_error_return_closes_array() {
+        fn write_then_fail(output: Shared, options: &Options) -> io::Result<()> {
+            let mut writer = StructuredWriter::new(output, options);
+            writer.write(&Pair { a: "goat", b: 1 })?;
+            writer.write(&Pair { a: "sheep", b: 2 })?;
+            "many goats".parse::<u8>().map_err(io::Error::other)?;
+            writer.finish()
+        }
+
+        let mut options = Options::with_defaults().unwrap();
+        options.output_format = OutputFormat::Json;
+        let shared = Shared::default();
+        assert!(write_then_fail(shared.clone(), &options).is_err());
+        let bytes = shared.0.borrow().clone();
+        let value: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(2, value.as_array().unwrap().len());
+    }
+}
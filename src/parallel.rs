@@ -0,0 +1,134 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! Running jobs on a bounded pool of worker threads, while keeping their
+//! results in order.
+
+use crate::shell::Options;
+use std::{
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    sync::{Mutex, mpsc},
+    thread,
+};
+
+/// Returns the number of jobs to run at once: 1, unless `options.parallel` is
+/// set, in which case `options.workers` or else the number of CPUs.
+pub fn worker_count(options: &Options) -> usize {
+    if !options.parallel {
+        return 1;
+    }
+    options
+        .workers
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get))
+}
+
+/// Holds results that arrive out of order until all the results before them
+/// have arrived.
+struct Reorderer<R, S: FnMut(R)> {
+    pending: BTreeMap<usize, R>,
+    next: usize,
+    sink: S,
+}
+
+impl<R, S: FnMut(R)> Reorderer<R, S> {
+    fn deliver(&mut self, i: usize, result: R) {
+        self.pending.insert(i, result);
+        while let Some(result) = self.pending.remove(&self.next) {
+            (self.sink)(result);
+            self.next += 1;
+        }
+    }
+}
+
+/// Calls `f` on each of the `items` using up to `workers` threads, and calls
+/// `sink` on each result in the same order as `items`.
+///
+/// `items` is consumed lazily: only a few items per worker are ever queued or
+/// waiting to be delivered, so `items` can be an arbitrarily long stream.
+pub fn ordered_map<T, R, F, S>(items: impl Iterator<Item = T>, workers: usize, f: F, sink: S)
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+    S: FnMut(R),
+{
+    if workers <= 1 {
+        items.map(f).for_each(sink);
+        return;
+    }
+
+    let (job_sender, job_receiver) = mpsc::sync_channel::<(usize, T)>(workers);
+    let job_receiver = Mutex::new(job_receiver);
+    let (result_sender, result_receiver) = mpsc::channel::<(usize, R)>();
+    let f = &f;
+    let mut reorderer = Reorderer {
+        pending: BTreeMap::new(),
+        next: 0,
+        sink,
+    };
+    let window = 2 * workers;
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let job_receiver = &job_receiver;
+            let result_sender = result_sender.clone();
+            scope.spawn(move || {
+                loop {
+                    let job = match job_receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => return,
+                    };
+                    let Ok((i, item)) = job else {
+                        return;
+                    };
+                    if result_sender.send((i, f(item))).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(result_sender);
+
+        for (i, item) in items.enumerate() {
+            // Don't let finished results pile up behind a slow job.
+            while i >= reorderer.next + window {
+                match result_receiver.recv() {
+                    Ok((j, result)) => reorderer.deliver(j, result),
+                    Err(_) => break,
+                }
+            }
+            if job_sender.send((i, item)).is_err() {
+                break;
+            }
+            while let Ok((j, result)) = result_receiver.try_recv() {
+                reorderer.deliver(j, result);
+            }
+        }
+        drop(job_sender);
+        for (j, result) in result_receiver {
+            reorderer.deliver(j, result);
+        }
+    });
+}
+
+#[test]
+fn test_ordered_map() {
+    use std::{thread::sleep, time::Duration};
+
+    for workers in [1, 2, 7] {
+        let mut results = Vec::new();
+        ordered_map(
+            (0..50_u64).rev(),
+            workers,
+            |n| {
+                // Make the early jobs finish last.
+                sleep(Duration::from_micros(n * 20));
+                n * 2
+            },
+            |n| results.push(n),
+        );
+        let expected: Vec<u64> = (0..50).rev().map(|n| n * 2).collect();
+        assert_eq!(expected, results);
+    }
+}
@@ -0,0 +1,355 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! Boolean expressions of the `-m`, `-p`, `-M`, and `-x` tests, for `filter`
+//! and `files`.
+
+use crate::{
+    shell::{Options, UsageError, new_regex},
+    time::Time,
+};
+use regex::bytes::Regex;
+use std::error::Error;
+
+/// A single test of a record or pathname.
+#[derive(Clone)]
+pub enum Term {
+    /// `m:regex`: the record matches `regex`.
+    Match(Regex),
+    /// `p:regex`: the record does not match `regex`. This is `!m:regex`, except
+    /// that `files` also prunes directories that match it; see `prunes`.
+    Prune(Regex),
+    /// `M:datetime`: the file’s modification time matches. Only `files` can
+    /// evaluate this.
+    Mtime(Time),
+    /// `x:command`: `command` exits with status 0.
+    Command(String),
+}
+
+impl Term {
+    /// Returns how expensive the term is to evaluate, relative to the others.
+    const fn cost(&self) -> u8 {
+        match self {
+            Self::Match(_) | Self::Prune(_) => 0,
+            Self::Mtime(_) => 1,
+            Self::Command(_) => 2,
+        }
+    }
+}
+
+/// A boolean expression of `Term`s. The operands of `And` and `Or` are sorted
+/// so that the cheapest are evaluated first, and evaluation short-circuits, so
+/// that (for example) an expensive command runs only if it can change the
+/// result.
+#[derive(Clone)]
+pub enum Predicate {
+    Term(Term),
+    Not(Box<Self>),
+    And(Vec<Self>),
+    Or(Vec<Self>),
+}
+
+impl Predicate {
+    /// Returns the conjunction of `predicates`, cheapest first. The empty
+    /// conjunction is true.
+    fn and(predicates: Vec<Self>) -> Self {
+        Self::sorted(predicates, Self::And)
+    }
+
+    /// Returns the disjunction of `predicates`, cheapest first.
+    fn or(predicates: Vec<Self>) -> Self {
+        Self::sorted(predicates, Self::Or)
+    }
+
+    fn sorted(mut predicates: Vec<Self>, new: fn(Vec<Self>) -> Self) -> Self {
+        if predicates.len() == 1 {
+            return predicates.remove(0);
+        }
+        predicates.sort_by_key(Self::cost);
+        new(predicates)
+    }
+
+    fn cost(&self) -> u8 {
+        match self {
+            Self::Term(t) => t.cost(),
+            Self::Not(p) => p.cost(),
+            Self::And(ps) | Self::Or(ps) => ps.iter().map(Self::cost).max().unwrap_or(0),
+        }
+    }
+
+    /// Returns the conjunction of all the `-p`, `-m`, `-M`, `-x`, and `-E`
+    /// tests in `options`.
+    pub fn from_options(options: &Options) -> Self {
+        let mut predicates = Vec::new();
+        predicates.extend(
+            options
+                .prune_expressions
+                .iter()
+                .map(|re| Self::Term(Term::Prune(re.clone()))),
+        );
+        predicates.extend(
+            options
+                .match_expressions
+                .iter()
+                .map(|re| Self::Term(Term::Match(re.clone()))),
+        );
+        predicates.extend(
+            options
+                .mtime_expressions
+                .iter()
+                .map(|t| Self::Term(Term::Mtime(t.clone()))),
+        );
+        predicates.extend(
+            options
+                .match_commands
+                .iter()
+                .map(|c| Self::Term(Term::Command(c.clone()))),
+        );
+        predicates.extend(options.expressions.iter().cloned());
+        Self::and(predicates)
+    }
+
+    /// Returns true if `f` is true for any of the terms.
+    pub fn any_term(&self, f: &impl Fn(&Term) -> bool) -> bool {
+        match self {
+            Self::Term(t) => f(t),
+            Self::Not(p) => p.any_term(f),
+            Self::And(ps) | Self::Or(ps) => ps.iter().any(|p| p.any_term(f)),
+        }
+    }
+
+    /// Returns true if `pathname` matches a `p:` (or `-p`) test that the whole
+    /// predicate depends on, i.e. one that is not inside a `|` or a `!`. That
+    /// is, the predicate is false for `pathname`, and `files` does not search
+    /// it if it is a directory.
+    pub fn prunes(&self, pathname: &[u8]) -> bool {
+        match self {
+            Self::Term(Term::Prune(re)) => re.is_match(pathname),
+            Self::And(ps) => ps.iter().any(|p| p.prunes(pathname)),
+            Self::Term(_) | Self::Not(_) | Self::Or(_) => false,
+        }
+    }
+
+    /// Evaluates the expression, calling `test` to evaluate each term that
+    /// can affect the result.
+    pub fn evaluate<'a>(&'a self, test: &mut dyn FnMut(&'a Term) -> bool) -> bool {
+        match self {
+            Self::Term(t) => test(t),
+            Self::Not(p) => !p.evaluate(test),
+            Self::And(ps) => ps.iter().all(|p| p.evaluate(test)),
+            Self::Or(ps) => ps.iter().any(|p| p.evaluate(test)),
+        }
+    }
+
+    /// Parses `s`, which is parsed as having come from a grammar not entirely
+    /// unlike:
+    ///
+    ///     or ::= and ( "|" and )*
+    ///     and ::= unary ( "&" unary )*
+    ///     unary ::= "!" unary | "(" or ")" | term
+    ///     term ::= ( "m" | "p" | "M" | "x" ) ":" argument
+    ///     argument ::= '"' characters '"' | characters
+    ///
+    /// An unquoted argument extends up to the next `&`, `|`, or `)`; to use
+    /// those characters, quote the argument (and escape any `"` or `\` in it
+    /// with `\`). Space around operators and arguments is ignored. Regular
+    /// expressions are case-sensitive as given with `-m` (see `-S`).
+    pub fn parse(s: &str, options: &Options) -> Result<Self, Box<dyn Error>> {
+        let mut parser = Parser { s, i: 0, options };
+        let predicate = parser.or()?;
+        parser.skip_space();
+        if parser.i < s.len() {
+            return Err(parser.error("expected `&`, `|`, or the end"));
+        }
+        Ok(predicate)
+    }
+}
+
+/// The state of `Predicate::parse`.
+struct Parser<'a> {
+    s: &'a str,
+    /// The byte offset of the next character to parse.
+    i: usize,
+    options: &'a Options,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.s[self.i..]
+    }
+
+    fn skip_space(&mut self) {
+        self.i = self.s.len() - self.rest().trim_start().len();
+    }
+
+    /// Skips space, and then `c` if it is next. Returns true if it was.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_space();
+        if self.rest().starts_with(c) {
+            self.i += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, expected: &str) -> Box<dyn Error> {
+        UsageError::new(&format!(
+            "invalid expression \"{}\" at {}: {expected}",
+            self.s, self.i
+        ))
+        .into()
+    }
+
+    fn or(&mut self) -> Result<Predicate, Box<dyn Error>> {
+        let mut predicates = vec![self.and()?];
+        while self.eat('|') {
+            predicates.push(self.and()?);
+        }
+        Ok(Predicate::or(predicates))
+    }
+
+    fn and(&mut self) -> Result<Predicate, Box<dyn Error>> {
+        let mut predicates = vec![self.unary()?];
+        while self.eat('&') {
+            predicates.push(self.unary()?);
+        }
+        Ok(Predicate::and(predicates))
+    }
+
+    fn unary(&mut self) -> Result<Predicate, Box<dyn Error>> {
+        if self.eat('!') {
+            return Ok(Predicate::Not(Box::new(self.unary()?)));
+        }
+        if self.eat('(') {
+            let predicate = self.or()?;
+            if !self.eat(')') {
+                return Err(self.error("expected `)`"));
+            }
+            return Ok(predicate);
+        }
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<Predicate, Box<dyn Error>> {
+        self.skip_space();
+        let Some((kind, _)) = self.rest().split_once(':') else {
+            return Err(self.error("expected m:, p:, M:, or x:"));
+        };
+        let kind = kind.to_string();
+        self.i += kind.len() + 1;
+        let argument = self.argument()?;
+        let term = match kind.as_str() {
+            "m" => Term::Match(new_regex(&argument, self.options)?),
+            "p" => Term::Prune(new_regex(&argument, self.options)?),
+            "M" => Term::Mtime(Time::new(&argument)?),
+            "x" => Term::Command(argument),
+            _ => return Err(self.error("expected m:, p:, M:, or x:")),
+        };
+        Ok(Predicate::Term(term))
+    }
+
+    fn argument(&mut self) -> Result<String, Box<dyn Error>> {
+        let s = self.s;
+        if !self.eat('"') {
+            let rest = &s[self.i..];
+            let end = rest.find(['&', '|', ')']).unwrap_or(rest.len());
+            self.i += end;
+            return Ok(rest[..end].trim().to_string());
+        }
+        let mut argument = String::new();
+        let mut chars = s[self.i..].char_indices();
+        while let Some((j, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.i += j + 1;
+                    return Ok(argument);
+                }
+                '\\' => match chars.next() {
+                    Some((_, c)) => argument.push(c),
+                    None => break,
+                },
+                c => argument.push(c),
+            }
+        }
+        Err(self.error("expected closing `\"`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluates `expression` on `record`, treating `x:` terms as true if
+    /// their command is `true`. Returns the result and the commands that
+    /// were run.
+    fn evaluate(expression: &str, record: &str) -> (bool, Vec<String>) {
+        let options = Options::with_defaults().unwrap();
+        let predicate = Predicate::parse(expression, &options).unwrap();
+        let mut run = Vec::new();
+        let result = predicate.evaluate(&mut |term| match term {
+            Term::Match(re) => re.is_match(record.as_bytes()),
+            Term::Prune(re) => !re.is_match(record.as_bytes()),
+            Term::Mtime(_) => false,
+            Term::Command(c) => {
+                run.push(c.clone());
+                c == "true"
+            }
+        });
+        (result, run)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!((true, vec![]), evaluate("m:goat", "billy goat"));
+        assert_eq!((false, vec![]), evaluate("p:goat", "billy goat"));
+        assert_eq!(
+            (true, vec![]),
+            evaluate("(m:sheep | m:goat) & !m:llama", "billy goat")
+        );
+        assert_eq!((false, vec![]), evaluate("!(m:sheep|m:goat)", "goat"));
+        assert_eq!(
+            (true, vec![]),
+            evaluate(r#"m:"a|b" & m:"\"q\"""#, "a|b \"q\"")
+        );
+    }
+
+    #[test]
+    fn test_commands_run_last() {
+        // The command is written first, but runs only if the regex matches.
+        assert_eq!((false, vec![]), evaluate("x:true & m:sheep", "goat"));
+        assert_eq!(
+            (true, vec![String::from("true")]),
+            evaluate("x:true & m:goat", "goat")
+        );
+        assert_eq!((true, vec![]), evaluate("x:false | m:goat", "goat"));
+        assert_eq!(
+            (false, vec![String::from("test -s")]),
+            evaluate("x:test -s | m:sheep", "goat")
+        );
+    }
+
+    #[test]
+    fn test_prunes() {
+        let options = Options::with_defaults().unwrap();
+        let prunes = |expression: &str, pathname: &str| {
+            Predicate::parse(expression, &options)
+                .unwrap()
+                .prunes(pathname.as_bytes())
+        };
+        assert!(prunes("p:target", "target"));
+        assert!(prunes("m:rs & (p:target & m:src)", "target"));
+        assert!(!prunes("p:target", "src"));
+        assert!(!prunes("!m:target", "target"));
+        assert!(!prunes("p:target | m:src", "target"));
+        assert!(!prunes("!p:target", "target"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let options = Options::with_defaults().unwrap();
+        for s in ["", "m:goat &", "(m:goat", "q:goat", "m:\"goat", "m:goat)"] {
+            assert!(Predicate::parse(s, &options).is_err(), "{s}");
+        }
+    }
+}
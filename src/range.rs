@@ -0,0 +1,79 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! Ranges of record numbers, as given to `records -L`.
+
+use std::str::FromStr;
+
+/// A range of record numbers: `start..end`, optionally selecting only every
+/// `step`th record (`start..end:step`). Either end can be omitted: `..10` is
+/// records 1 through 9, and `10..` is record 10 onward. A single number
+/// `n` is the same as `n..n+1`. Record numbers start at 1, and `end` is
+/// exclusive, as in Rust.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Range {
+    pub start: usize,
+    pub end: Option<usize>,
+    pub step: usize,
+}
+
+impl Range {
+    /// Returns true if the record numbered `i` is in the range.
+    pub const fn contains(&self, i: usize) -> bool {
+        i >= self.start
+            && match self.end {
+                Some(end) => i < end,
+                None => true,
+            }
+            && (i - self.start).is_multiple_of(self.step)
+    }
+}
+
+impl FromStr for Range {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid range \"{s}\" (use e.g. 10, 10..20, 10.., or ..20:2)");
+        let number = |n: &str| n.trim().parse::<usize>().map_err(|_| invalid());
+        let (range, step) = match s.split_once(':') {
+            Some((range, step)) => (range, number(step)?),
+            None => (s, 1),
+        };
+        let (start, end) = if let Some((start, end)) = range.split_once("..") {
+            (
+                if start.is_empty() { 1 } else { number(start)? },
+                if end.is_empty() {
+                    None
+                } else {
+                    Some(number(end)?)
+                },
+            )
+        } else {
+            let n = number(range)?;
+            (n, Some(n.checked_add(1).ok_or_else(invalid)?))
+        };
+        // Records are numbered from 1.
+        if start == 0 || step == 0 {
+            return Err(invalid());
+        }
+        Ok(Self { start, end, step })
+    }
+}
+
+#[test]
+fn test_range() {
+    let selected = |s: &str| {
+        let range = s.parse::<Range>().unwrap();
+        (1..=12).filter(|i| range.contains(*i)).collect::<Vec<_>>()
+    };
+    assert_eq!(vec![3], selected("3"));
+    assert_eq!(vec![2, 3, 4], selected("2..5"));
+    assert_eq!(vec![1], selected("..2"));
+    assert_eq!(vec![10, 11, 12], selected("10.."));
+    assert_eq!(vec![1, 6, 11], selected("..:5"));
+    assert_eq!(vec![1, 4], selected("1..7:3"));
+    let max = usize::MAX.to_string();
+    for s in ["", "x", "1..x", "..:0", "-1..", "0", "0..", "0..5", &max] {
+        assert!(s.parse::<Range>().is_err(), "{s}");
+    }
+}
@@ -5,19 +5,198 @@
 
 use crate::{
     enumerated_record::EnumeratedRecord,
-    shell::{FileOpener, STDIN_PATHNAME, ShellResult, parse_options},
-    util::{exit_with_result, help},
+    output::{OutputFormat, StructuredWriter},
+    shell::{
+        FileOpener, Options, Record, STDIN_PATHNAME, ShellResult, UsageError, file_records,
+        parse_options, split_records, splits_records,
+    },
+    util::{exit_with_result, help, spawn_with_stdin},
+};
+use regex::bytes::Regex;
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    ffi::OsString,
+    io::{self, ErrorKind, stdout},
+    str::FromStr,
 };
-use itertools::Either;
-use regex_splitter::RegexSplitter;
-use std::io::{IsTerminal, Write, stdout};
 
 pub const RECORDS_HELP: &str = include_str!("records.md");
 pub const RECORDS_HELP_VERBOSE: &str = include_str!("records_verbose.md");
 
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "CeFHhJjLlnoRrSvxZ";
+
+/// What to do with the first record of each file, given with `-H`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HeaderMode {
+    /// Do not print it.
+    Suppress,
+    /// Print the first file's header as it is, and skip the others.
+    Pass,
+    /// Print each file's header as it is.
+    Repeat,
+}
+
+impl FromStr for HeaderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "suppress" => Ok(Self::Suppress),
+            "pass" => Ok(Self::Pass),
+            "repeat" => Ok(Self::Repeat),
+            _ => Err(format!(
+                "invalid header mode \"{s}\" (use suppress, pass, or repeat)"
+            )),
+        }
+    }
+}
+
+/// The size of the first block that `tail` reads from the end of a file.
+const TAIL_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Returns the last `n` of `records`, keeping no more than `n` in memory.
+fn last<T>(records: impl Iterator<Item = T>, n: usize) -> VecDeque<T> {
+    let mut last = VecDeque::with_capacity(n);
+    for r in records {
+        if last.len() == n {
+            last.pop_front();
+        }
+        last.push_back(r);
+    }
+    last
+}
+
+/// Returns the last `n` records of the memory-mapped file `map`, split with
+/// `delimiter`. Reads backwards from the end in blocks (starting with
+/// `block_size` bytes, and doubling), so that it need not read all of a large
+/// file.
+fn tail<'a>(map: &'a [u8], n: usize, delimiter: &Regex, block_size: usize) -> VecDeque<&'a [u8]> {
+    let mut block_size = block_size;
+    loop {
+        let start = map.len().saturating_sub(block_size);
+        let mut records = split_records(&map[start..], delimiter);
+        if start == 0 {
+            return last(records, n);
+        }
+        // The first record in the block may be only the end of a record, so
+        // we need more than `n`.
+        records.next();
+        let records = last(records, n + 1);
+        if records.len() > n {
+            return records.into_iter().skip(1).collect();
+        }
+        block_size *= 2;
+    }
+}
+
+/// Splits the memory-mapped file `map` into its first record and the rest.
+fn split_header<'a>(map: &'a [u8], delimiter: &Regex) -> (&'a [u8], &'a [u8]) {
+    delimiter
+        .find(map)
+        .map_or((map, &[]), |m| (&map[..m.start()], &map[m.end()..]))
+}
+
+/// Returns the `records` that are in any of the `-L` ranges (if any), and then
+/// within the `-l` limit (if any). `map` is the memory-mapped file that
+/// `records` come from, if any.
+fn select<'a: 'b, 'b>(
+    records: impl Iterator<Item = Record<'a>> + 'b,
+    map: Option<&'a [u8]>,
+    options: &'b Options,
+) -> Box<dyn Iterator<Item = Record<'a>> + 'b> {
+    let ranges = &options.ranges;
+    let records: Box<dyn Iterator<Item = Record<'a>> + 'b> = if ranges.is_empty() {
+        Box::new(records)
+    } else {
+        // If every range has an end, there is no need to read past the last
+        // one.
+        let records: Box<dyn Iterator<Item = Record<'a>> + 'b> = match ranges
+            .iter()
+            .try_fold(0, |e, r| r.end.map(|end| e.max(end)))
+        {
+            Some(end) => Box::new(records.take(end.saturating_sub(1))),
+            None => Box::new(records),
+        };
+        Box::new(
+            records
+                .enumerate()
+                .filter(move |(i, _)| ranges.iter().any(|r| r.contains(i + 1)))
+                .map(|(_, r)| r),
+        )
+    };
+
+    match options.limit {
+        Some(limit) if limit < 0 => {
+            let n = limit.unsigned_abs();
+            match map.filter(|_| ranges.is_empty()) {
+                Some(map) => Box::new(
+                    tail(map, n, &options.input_record_delimiter, TAIL_BLOCK_SIZE)
+                        .into_iter()
+                        .map(|r| Record::whole(Cow::Borrowed(r))),
+                ),
+                None => Box::new(last(records, n).into_iter()),
+            }
+        }
+        Some(limit) => Box::new(records.take(limit.unsigned_abs())),
+        None => records,
+    }
+}
+
+/// Ignores `SIGPIPE` until dropped, and then restores its previous handler.
+/// (`main` resets it to `SIG_DFL`, which would kill us if a `-x` command exits
+/// without reading all its input.)
+struct SigpipeIgnored(libc::sighandler_t);
+
+impl SigpipeIgnored {
+    fn new() -> Self {
+        Self(unsafe { libc::signal(libc::SIGPIPE, libc::SIG_IGN) })
+    }
+}
+
+impl Drop for SigpipeIgnored {
+    fn drop(&mut self) {
+        unsafe {
+            libc::signal(libc::SIGPIPE, self.0);
+        }
+    }
+}
+
+/// Writes `records` to the standard input of a new instance of `command`, and
+/// waits for it to finish. Returns 1 if `command` failed, or 0 if it
+/// succeeded. It is not an error for `command` to exit without reading all
+/// the records (as e.g. `head` does).
+fn pipe<'a>(
+    command: &str,
+    records: impl Iterator<Item = EnumeratedRecord<'a>>,
+    options: &Options,
+) -> io::Result<i32> {
+    let mut child = spawn_with_stdin(command)?;
+    let written = {
+        // A write to a `command` that has exited fails with `BrokenPipe`,
+        // meaning that it has had enough input.
+        let _sigpipe = SigpipeIgnored::new();
+        child.stdin.take().map_or(Ok(()), |stdin| {
+            let mut output = StructuredWriter::new(stdin, options);
+            for er in records {
+                output.write(&er)?;
+            }
+            output.finish()
+        })
+    };
+    // Wait even if writing failed, so that `command` does not outlive us.
+    // Dropping the writer has closed its input, so it will not wait for more.
+    let status = child.wait()?;
+    match written {
+        Err(error) if error.kind() != ErrorKind::BrokenPipe => Err(error),
+        _ => Ok(i32::from(!status.success())),
+    }
+}
+
 /// Runs the `records` command on `arguments`.
-pub fn records_main(arguments: &[String]) -> ShellResult {
-    let (options, arguments) = parse_options(arguments)?;
+pub fn records_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
     if options.help {
         exit_with_result(help(
             0,
@@ -31,59 +210,65 @@ pub fn records_main(arguments: &[String]) -> ShellResult {
         ));
     }
 
+    if options.match_commands.len() > 1 {
+        return Err(UsageError::new("records takes at most 1 -x command").into());
+    }
+    if !options.match_commands.is_empty() && options.output_format != OutputFormat::Columns {
+        return Err(UsageError::new("records -x works only with the columns output format").into());
+    }
+
     let mut status = 0;
-    let mut stdout = stdout();
-    for file in FileOpener::new(arguments) {
+    let mut header_written = false;
+    let mut output = StructuredWriter::new(stdout(), &options);
+    for file in FileOpener::new(arguments, &options).mapped() {
         let pathname = file.pathname.unwrap_or(&STDIN_PATHNAME);
         match file.read {
             Ok(mut read) => {
-                let records = RegexSplitter::new(&mut read, &options.input_record_delimiter)
-                    .map_while(Result::ok);
-                let records = match options.limit {
-                    Some(limit) => {
-                        Either::Right(if limit >= 0 {
-                            Either::Right(records.take(limit as usize))
-                        } else {
-                            // TODO: For best efficiency — not reading the
-                            // entire stream first when not necessary — we will
-                            // probably want to implement `DoubleEndedIterator`
-                            // for `RegexSplitter`.
-                            Either::Left(
-                                records
-                                    .collect::<Vec<Vec<u8>>>()
-                                    .into_iter()
-                                    .rev()
-                                    .take(limit.unsigned_abs())
-                                    .rev(),
-                            )
-                        })
+                let map = file.map.as_deref().filter(|_| splits_records(&options));
+                let (header, map) = match map {
+                    Some(map) if options.header.is_some() => {
+                        let (header, rest) = split_header(map, &options.input_record_delimiter);
+                        (Some(Record::whole(Cow::Borrowed(header))), Some(rest))
                     }
-                    None => Either::Left(records),
+                    _ => (None, map),
                 };
+                let mut records = file_records(map, &mut read, pathname, &options);
+                let header = header.or_else(|| options.header.and_then(|_| records.next()));
 
-                if options.json_output {
-                    println!("[");
+                if let Some(Record { r, fields }) = header
+                    && (options.header == Some(HeaderMode::Repeat)
+                        || (options.header == Some(HeaderMode::Pass) && !header_written))
+                {
+                    output.write(&EnumeratedRecord {
+                        n: None,
+                        pathname,
+                        r,
+                        fields,
+                    })?;
+                    header_written = true;
                 }
-                for er in records.enumerate().map(|pair| EnumeratedRecord {
-                    n: if options.no_enumerate {
-                        None
-                    } else {
-                        Some(pair.0)
-                    },
-                    pathname,
-                    r: pair.1,
-                }) {
-                    if options.json_output {
-                        let t = stdout.is_terminal();
-                        er.write_json(&mut stdout, t, &options)?;
-                        stdout.write_all(b",\n")?;
-                    } else {
-                        er.write_columns(&mut stdout, &options)?;
+
+                let selected = select(&mut records, map, &options)
+                    .enumerate()
+                    .map(|(n, record)| EnumeratedRecord {
+                        n: if options.no_enumerate { None } else { Some(n) },
+                        pathname,
+                        r: record.r,
+                        fields: record.fields,
+                    })
+                    .filter(|er| options.print_empty || !er.r.is_empty());
+                match options.match_commands.first() {
+                    Some(command) => {
+                        output.flush()?;
+                        status += pipe(command, selected, &options)?;
+                    }
+                    None => {
+                        for er in selected {
+                            output.write(&er)?;
+                        }
                     }
                 }
-                if options.json_output {
-                    println!("{{}}]");
-                }
+                status += records.errors;
             }
             Err(error) => {
                 eprintln!("{pathname}: {error}");
@@ -91,5 +276,33 @@ pub fn records_main(arguments: &[String]) -> ShellResult {
             }
         }
     }
+    output.finish()?;
     Ok(status)
 }
+
+#[test]
+fn test_tail() {
+    let delimiter = Regex::new(r"\n").unwrap();
+    let map = b"goat\nsheep\nllama\nyak\n";
+    for block_size in [1, 2, 5, 100] {
+        assert_eq!(
+            vec![b"llama".as_slice(), b"yak".as_slice()],
+            Vec::from(tail(map, 2, &delimiter, block_size))
+        );
+        assert_eq!(4, tail(map, 10, &delimiter, block_size).len());
+    }
+    assert_eq!(vec![3, 4], Vec::from(last(1..=4, 2)));
+}
+
+#[test]
+fn test_split_header() {
+    let delimiter = Regex::new(r"\n").unwrap();
+    assert_eq!(
+        (b"Animal".as_slice(), b"goat\nsheep\n".as_slice()),
+        split_header(b"Animal\ngoat\nsheep\n", &delimiter)
+    );
+    assert_eq!(
+        (b"Animal".as_slice(), b"".as_slice()),
+        split_header(b"Animal", &delimiter)
+    );
+}
@@ -0,0 +1,302 @@
+// Copyright 2022 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `futils reduce` command.
+
+use crate::{
+    fields::{parse_fields, select_fields},
+    output::{StructuredWrite, StructuredWriter},
+    shell::{FileOpener, Options, STDIN_PATHNAME, ShellResult, parse_options, read_records},
+    util::{StrOrBytes, command_output, exit_with_result, help, parse_number, print_output},
+};
+use bigdecimal::{BigDecimal, Zero};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    error::Error,
+    ffi::OsString,
+    io::{self, Write, stdout},
+    iter::zip,
+};
+
+pub const REDUCE_HELP: &str = include_str!("reduce.md");
+pub const REDUCE_HELP_VERBOSE: &str = include_str!("reduce_verbose.md");
+
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "a(columns)cFfhJjoRrSvxZ";
+
+/// How to combine the values of a column into a single value.
+#[derive(Clone, Copy)]
+enum Fold<'a> {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Min,
+    Max,
+    Mean,
+    Count,
+    /// Run the command with the accumulator and the next value as its
+    /// arguments. Its `stdout` is the new accumulator.
+    Command(&'a str),
+}
+
+impl<'a> Fold<'a> {
+    fn new(command: &'a str) -> Self {
+        match command {
+            "+" | "sum" => Self::Add,
+            "-" => Self::Subtract,
+            "*" | "product" => Self::Multiply,
+            "/" => Self::Divide,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "mean" => Self::Mean,
+            "count" => Self::Count,
+            command => Self::Command(command),
+        }
+    }
+}
+
+/// Returns the result of running `command` with `accumulator` and `value` as
+/// its arguments, minus any trailing newline.
+fn fold_command(
+    command: &str,
+    accumulator: &[u8],
+    value: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = command_output(command, &[accumulator, value])?;
+    if print_output(&output, false)? != 0 {
+        return Err(format!("{command}: {}", output.status).into());
+    }
+    let mut result = output.stdout;
+    if result.last() == Some(&b'\n') {
+        result.pop();
+    }
+    Ok(result)
+}
+
+/// The result so far of 1 `Fold` over 1 column.
+struct Accumulator<'a> {
+    fold: Fold<'a>,
+    number: Option<BigDecimal>,
+    bytes: Option<Vec<u8>>,
+    count: u64,
+}
+
+impl<'a> Accumulator<'a> {
+    const fn new(fold: Fold<'a>) -> Self {
+        Self {
+            fold,
+            number: None,
+            bytes: None,
+            count: 0,
+        }
+    }
+
+    /// Folds `value` into the accumulator.
+    fn add(&mut self, value: &[u8]) -> Result<(), Box<dyn Error>> {
+        match self.fold {
+            Fold::Count => {}
+            Fold::Command(command) => {
+                self.bytes = Some(match &self.bytes {
+                    None => value.to_vec(),
+                    Some(accumulator) => fold_command(command, accumulator, value)?,
+                });
+            }
+            fold => {
+                let b = parse_number(value)?;
+                if matches!(fold, Fold::Divide) && self.number.is_some() && b.is_zero() {
+                    return Err("division by zero".into());
+                }
+                self.number = Some(match self.number.take() {
+                    None => b,
+                    Some(a) => match fold {
+                        Fold::Add | Fold::Mean => a + b,
+                        Fold::Subtract => a - b,
+                        Fold::Multiply => a * b,
+                        Fold::Divide => a / b,
+                        Fold::Min => a.min(b),
+                        Fold::Max => a.max(b),
+                        Fold::Count | Fold::Command(_) => unreachable!(),
+                    },
+                });
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Returns the result of the fold, or the empty string if there was no
+    /// input.
+    fn result(&self) -> Vec<u8> {
+        match self.fold {
+            Fold::Count => self.count.to_string().into_bytes(),
+            Fold::Command(_) => self.bytes.clone().unwrap_or_default(),
+            Fold::Mean => self.number.as_ref().map_or_else(Vec::new, |n| {
+                format!("{}", n / BigDecimal::from(self.count)).into_bytes()
+            }),
+            _ => self
+                .number
+                .as_ref()
+                .map_or_else(Vec::new, |n| format!("{n}").into_bytes()),
+        }
+    }
+}
+
+/// The results of 1 `-x` fold over all the columns.
+#[derive(Serialize)]
+struct Reduced<'a> {
+    x: &'a str,
+    values: Vec<StrOrBytes<'a>>,
+}
+
+impl StructuredWrite for Reduced<'_> {
+    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> Result<(), io::Error> {
+        for (i, value) in self.values.iter().enumerate() {
+            if i > 0 {
+                output.write_all(&options.output_field_delimiter)?;
+            }
+            output.write_all(value.0)?;
+        }
+        output.write_all(&options.output_record_delimiter)
+    }
+
+    fn to_fields(&self, _: &Options) -> Vec<Cow<'_, [u8]>> {
+        self.values.iter().map(|v| Cow::Borrowed(v.0)).collect()
+    }
+}
+
+/// Applies each of the `-x` folds to each column of input.
+struct Reducer<'a> {
+    commands: &'a [String],
+    /// `accumulators[i][j]` is the result so far of the `i`th fold over the
+    /// `j`th column.
+    accumulators: Vec<Vec<Accumulator<'a>>>,
+}
+
+impl<'a> Reducer<'a> {
+    fn new(commands: &'a [String]) -> Self {
+        Self {
+            commands,
+            accumulators: commands.iter().map(|_| Vec::new()).collect(),
+        }
+    }
+
+    /// Makes sure there are accumulators for at least `columns` columns.
+    fn grow(&mut self, columns: usize) {
+        for (command, accumulators) in zip(self.commands, &mut self.accumulators) {
+            while accumulators.len() < columns {
+                accumulators.push(Accumulator::new(Fold::new(command)));
+            }
+        }
+    }
+
+    /// Folds `value` into each of the accumulators for `column`. Returns the
+    /// number of folds that failed.
+    fn add(&mut self, column: usize, value: &[u8]) -> i32 {
+        let mut status = 0;
+        for accumulators in &mut self.accumulators {
+            if let Err(error) = accumulators[column].add(value) {
+                eprintln!("{}: {error}", String::from_utf8_lossy(value));
+                status += 1;
+            }
+        }
+        status
+    }
+
+    /// Folds `record` into the accumulators: as a whole if `columns` is
+    /// `None` and `options.show_all` is false, or else field by field. Empty
+    /// fields are skipped.
+    fn add_record(&mut self, record: &[u8], columns: Option<&[isize]>, options: &Options) -> i32 {
+        if columns.is_none() && !options.show_all {
+            return self.add(0, record);
+        }
+
+        let fields = options
+            .input_field_delimiter
+            .split(record)
+            .collect::<Vec<&[u8]>>();
+        let fields: Vec<Option<&[u8]>> = match columns {
+            Some(columns) => columns
+                .iter()
+                .map(|c| select_fields(&fields, &[*c], false).first().copied())
+                .collect(),
+            None => fields.into_iter().map(Some).collect(),
+        };
+        self.grow(fields.len());
+        let mut status = 0;
+        for (i, field) in fields.into_iter().enumerate() {
+            if let Some(field) = field.filter(|f| !f.is_empty()) {
+                status += self.add(i, field);
+            }
+        }
+        status
+    }
+
+    /// Prints 1 record for each fold, with 1 field for each column.
+    fn write(&self, output: &mut StructuredWriter) -> Result<(), io::Error> {
+        for (command, accumulators) in zip(self.commands, &self.accumulators) {
+            let values = accumulators
+                .iter()
+                .map(Accumulator::result)
+                .collect::<Vec<Vec<u8>>>();
+            output.write(&Reduced {
+                x: command,
+                values: values.iter().map(|v| StrOrBytes(v)).collect(),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the `reduce` command on `arguments`.
+pub fn reduce_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
+    if options.help {
+        exit_with_result(help(
+            0,
+            REDUCE_HELP,
+            true,
+            if options.verbose {
+                Some(REDUCE_HELP_VERBOSE)
+            } else {
+                None
+            },
+        ));
+    }
+    if options.match_commands.is_empty() {
+        exit_with_result(help(-1, REDUCE_HELP, false, None));
+    }
+
+    let columns = if options.fields.is_empty() {
+        None
+    } else {
+        Some(parse_fields(&options.fields)?)
+    };
+    let mut reducer = Reducer::new(&options.match_commands);
+    if columns.is_none() && !options.show_all {
+        reducer.grow(1);
+    }
+
+    let mut status = 0;
+    for file in FileOpener::new(arguments, &options) {
+        let pathname = file.pathname.unwrap_or(&STDIN_PATHNAME);
+        match file.read {
+            Ok(mut read) => {
+                for record in read_records(&mut read, pathname, &options) {
+                    if !record.is_empty() {
+                        status += reducer.add_record(&record, columns.as_deref(), &options);
+                    }
+                }
+            }
+            Err(error) => {
+                eprintln!("{pathname}: {error}");
+                status += 1;
+            }
+        }
+    }
+    let mut output = StructuredWriter::new(stdout(), &options);
+    reducer.write(&mut output)?;
+    output.finish()?;
+    Ok(status)
+}
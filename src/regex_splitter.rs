@@ -0,0 +1,260 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! Splitting streams into records with a regular expression.
+
+use regex::bytes::Regex;
+use regex_automata::{
+    Anchored, Input,
+    hybrid::dfa::{Cache, DFA},
+    nfa::thompson,
+    util::syntax,
+};
+use std::io::{self, ErrorKind, Read};
+
+/// How many bytes `RegexSplitter` reads at a time.
+const READ_SIZE: usize = 64 * 1024;
+
+/// Answers whether a delimiter match could be extended by more input, using a
+/// lazy DFA built from the delimiter’s pattern.
+struct Extension {
+    dfa: DFA,
+    cache: Cache,
+}
+
+impl Extension {
+    fn new(delimiter: &Regex) -> Option<Self> {
+        let dfa = DFA::builder()
+            .syntax(syntax::Config::new().utf8(false))
+            .thompson(thompson::Config::new().utf8(false))
+            .build(delimiter.as_str())
+            .ok()?;
+        let cache = dfa.create_cache();
+        Some(Self { dfa, cache })
+    }
+
+    /// Returns true if the delimiter match `matched` could be the start of a
+    /// longer match. Errs on the side of true.
+    fn possible(&mut self, matched: &[u8]) -> bool {
+        let (dfa, cache) = (&self.dfa, &mut self.cache);
+        let input = Input::new(matched).anchored(Anchored::Yes);
+        let Ok(mut state) = dfa.start_state_forward(cache, &input) else {
+            return true;
+        };
+        for b in matched {
+            match dfa.next_state(cache, state, *b) {
+                Ok(next) => state = next,
+                Err(_) => return true,
+            }
+        }
+        // The DFA reports a match 1 byte after it ends, so a state that is
+        // still alive after 1 more byte may only be reporting `matched`. One
+        // that is alive after 2 has a longer match in progress.
+        state.is_dead()
+            || (0..=u8::MAX).any(|b| match dfa.next_state(cache, state, b) {
+                Ok(next) if next.is_dead() => false,
+                Ok(next) => (0..=u8::MAX).any(|b| {
+                    dfa.next_state(cache, next, b)
+                        .map_or(true, |after| !after.is_dead())
+                }),
+                Err(_) => true,
+            })
+    }
+}
+
+/// An `Iterator` over the records in a `Read`, delimited by matches of a
+/// `Regex`. Like `Regex::split`, but reads the stream incrementally, so that
+/// records are available as soon as their delimiter has been read. Yields no
+/// empty record after a final delimiter.
+///
+/// A delimiter match that ends where the input read so far ends is accepted
+/// only if more input could not extend it, so that a delimiter like `\n+` or
+/// `\r\n|\r` is not cut short by the end of a read, while a record ending in
+/// `\n` is available at once.
+pub struct RegexSplitter<'a, R: Read> {
+    reader: R,
+    delimiter: &'a Regex,
+    /// If `None`, no match at the end of the buffer is accepted until more
+    /// input arrives.
+    extension: Option<Extension>,
+    buffer: Vec<u8>,
+    /// The offset in `buffer` of the start of the next record.
+    start: usize,
+    /// `reader` has reached the end of its input (or failed).
+    done: bool,
+}
+
+impl<'a, R: Read> RegexSplitter<'a, R> {
+    pub fn new(reader: R, delimiter: &'a Regex) -> Self {
+        Self {
+            reader,
+            delimiter,
+            extension: Extension::new(delimiter),
+            buffer: Vec::new(),
+            start: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the end of the next record, and the start of the one after it,
+    /// if the buffer holds a whole delimiter.
+    fn find_delimiter(&mut self) -> Option<(usize, usize)> {
+        let mut from = self.start;
+        while from <= self.buffer.len() {
+            let m = self.delimiter.find_at(&self.buffer, from)?;
+            if m.end() == self.buffer.len()
+                && !self.done
+                && self
+                    .extension
+                    .as_mut()
+                    .is_none_or(|e| e.possible(m.as_bytes()))
+            {
+                return None;
+            }
+            // An empty match at the start of the record would make an empty
+            // record, again and again.
+            if m.is_empty() && m.start() == self.start {
+                from = m.start() + 1;
+                continue;
+            }
+            return Some((m.start(), m.end()));
+        }
+        None
+    }
+
+    /// Reads more input into the buffer, first discarding the records that
+    /// have already been returned.
+    fn fill(&mut self) -> io::Result<()> {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+        let length = self.buffer.len();
+        self.buffer.resize(length + READ_SIZE, 0);
+        let result = loop {
+            match self.reader.read(&mut self.buffer[length..]) {
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                result => break result,
+            }
+        };
+        match result {
+            Ok(n) => {
+                self.buffer.truncate(length + n);
+                self.done = n == 0;
+                Ok(())
+            }
+            Err(error) => {
+                self.buffer.truncate(length);
+                self.done = true;
+                Err(error)
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for RegexSplitter<'_, R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((end, next)) = self.find_delimiter() {
+                let record = self.buffer[self.start..end].to_vec();
+                self.start = next;
+                return Some(Ok(record));
+            }
+            if self.done {
+                if self.start == self.buffer.len() {
+                    return None;
+                }
+                let record = self.buffer[self.start..].to_vec();
+                self.start = self.buffer.len();
+                return Some(Ok(record));
+            }
+            if let Err(error) = self.fill() {
+                return Some(Err(error));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns its chunks, 1 per `read`.
+    struct Chunks(Vec<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.0.remove(0);
+            buffer[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    fn split(chunks: &[&'static [u8]], delimiter: &str) -> Vec<Vec<u8>> {
+        let delimiter = Regex::new(delimiter).unwrap();
+        RegexSplitter::new(Chunks(chunks.to_vec()), &delimiter)
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn test_regex_splitter() {
+        let expected = vec![b"goat".to_vec(), b"sheep".to_vec(), b"llama".to_vec()];
+        assert_eq!(expected, split(&[b"goat\nsheep\nllama\n"], r"\n"));
+        assert_eq!(expected, split(&[b"goat\nsheep\nllama"], r"\n"));
+        assert_eq!(
+            expected,
+            split(&[b"go", b"at\nsh", b"eep\n", b"llama"], r"\n")
+        );
+        assert_eq!(
+            vec![b"goat".to_vec(), Vec::new(), b"sheep".to_vec()],
+            split(&[b"goat\n\nsheep\n"], r"\n")
+        );
+        assert!(split(&[], r"\n").is_empty());
+        assert_eq!(vec![Vec::<u8>::new()], split(&[b"\n"], r"\n"));
+    }
+
+    #[test]
+    fn test_delimiter_across_reads() {
+        let expected = vec![b"goat".to_vec(), b"sheep".to_vec()];
+        assert_eq!(expected, split(&[b"goat\r", b"\nsheep\r\n"], r"\r?\n"));
+        assert_eq!(expected, split(&[b"goat\n", b"\n\nsheep"], r"\n+"));
+        assert_eq!(
+            expected,
+            split(&[b"goat\r", b"\nsheep\r\n"], r"(\r\n|\n|\r)")
+        );
+    }
+
+    #[test]
+    fn test_record_before_more_input() {
+        // Fails every `read` after its chunks, as if more input had not yet
+        // arrived.
+        struct Stalled(Chunks);
+
+        impl Read for Stalled {
+            fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+                if self.0.0.is_empty() {
+                    return Err(io::Error::other("stalled"));
+                }
+                self.0.read(buffer)
+            }
+        }
+
+        let first = |chunk: &'static [u8], delimiter: &str| {
+            let delimiter = Regex::new(delimiter).unwrap();
+            RegexSplitter::new(Stalled(Chunks(vec![chunk])), &delimiter)
+                .next()
+                .unwrap()
+                .ok()
+        };
+        assert_eq!(Some(b"goat".to_vec()), first(b"goat\n", r"\n"));
+        assert_eq!(Some(b"goat".to_vec()), first(b"goat\n", r"(\r\n|\n|\r)"));
+        assert_eq!(Some(b"goat".to_vec()), first(b"goat\r\n", r"\r?\n"));
+        // These delimiters could go on.
+        assert_eq!(None, first(b"goat\r", r"(\r\n|\n|\r)"));
+        assert_eq!(None, first(b"goat\n", r"\n+"));
+    }
+}
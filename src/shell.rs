@@ -4,16 +4,33 @@
 //! A simple framework for command line programs: error types, option parsing,
 //! and assorted gadgets.
 
-use crate::{time::Time, util::unescape_backslashes};
-use getopt::Opt;
-use once_cell::sync::Lazy;
+use crate::{
+    config::configure,
+    csv_records::csv_records,
+    decompress::{decompress, is_compressed},
+    json::{JsonRecord, JsonRecords},
+    output::OutputFormat,
+    predicate::Predicate,
+    range::Range,
+    records::HeaderMode,
+    regex_splitter::RegexSplitter,
+    time::Time,
+    util::{file_name, unescape_backslashes},
+};
+use bstr::{BStr, ByteSlice};
+use memmap2::Mmap;
 use regex::bytes::{Regex, RegexBuilder};
 use std::{
+    borrow::Cow,
     error::Error,
+    ffi::OsString,
     fmt::{self, Debug, Display, Formatter},
     fs::File,
-    io::{self, Read, Write, stdin},
+    io::{self, Read, stdin},
+    iter,
+    os::unix::ffi::OsStrExt,
     str,
+    sync::LazyLock,
 };
 
 /// Return this error for invalid invocations of shell commands.
@@ -57,23 +74,71 @@ pub type ShellResult = Result<i32, Box<dyn Error>>;
 /// A synonym for convenience.
 pub type EmptyResult = Result<(), Box<dyn Error>>;
 
-/// The default list of command line flags. See `Options`, below.
-pub const DEFAULT_OPTION_SPEC: &str = "ad:c:eF:f:hIJjl:M:m:nP:p:R:r:Sst:vx:";
+/// A command line option: its short name, its long name, and whether it takes
+/// an argument.
+pub type OptionSpec = (char, &'static str, bool);
+
+/// All the command line options that `futils` programs understand. Each
+/// program accepts only those that it uses; see `parse_options`. See also
+/// `Options`, below.
+pub const OPTION_SPECS: &[OptionSpec] = &[
+    ('a', "all", false),
+    ('b', "buffer-size", true),
+    ('C', "csv-input", false),
+    ('c', "field", true),
+    ('d', "depth", true),
+    ('E', "expression", true),
+    ('e', "print-empty", false),
+    ('F', "output-field-delimiter", true),
+    ('f', "field-delimiter", true),
+    ('H', "header", true),
+    ('h', "help", false),
+    ('I', "invert", false),
+    ('J', "json", false),
+    ('j', "json-input", false),
+    ('k', "kind", true),
+    ('L', "range", true),
+    ('l', "limit", true),
+    ('M', "mtime", true),
+    ('m', "match", true),
+    ('n', "no-enumerate", false),
+    ('o', "output-format", true),
+    ('P', "parallel", false),
+    ('p', "prune", true),
+    ('R', "output-record-delimiter", true),
+    ('r', "record-delimiter", true),
+    ('S', "case-sensitive", false),
+    ('s', "skip-space", false),
+    ('t', "types", true),
+    ('v', "verbose", false),
+    ('w', "workers", true),
+    ('x', "command", true),
+    ('Z', "no-decompress", false),
+];
 
 /// These are the standard command line options for `futils` programs.
 ///
-/// Not all programs use all options. Some programs may not use this option
-/// spec, depending on their needs.
+/// Not all programs use all options; each program tells `parse_options` which
+/// ones it accepts.
 pub struct Options {
     /// `-a`
     pub show_all: bool,
 
+    /// `-b`
+    pub buffer_size: Option<usize>,
+
+    /// `-C`
+    pub csv_input: bool,
+
     /// `-c` (“column”, “cut”)
     pub fields: Vec<String>,
 
     /// `-d`
     pub depth: usize,
 
+    /// `-E`
+    pub expressions: Vec<Predicate>,
+
     /// `-e`
     pub print_empty: bool,
 
@@ -83,18 +148,24 @@ pub struct Options {
     /// `-f`
     pub input_field_delimiter: Regex,
 
+    /// `-H`
+    pub header: Option<HeaderMode>,
+
     /// `-h`
     pub help: bool,
 
     /// `-I`
     pub invert_fields: bool,
 
-    /// `-J`
-    pub json_output: bool,
-
     /// `-j`
     pub json_input: bool,
 
+    /// `-k`
+    pub kind: Option<String>,
+
+    /// `-L`
+    pub ranges: Vec<Range>,
+
     /// `-l`
     pub limit: Option<isize>,
 
@@ -107,6 +178,9 @@ pub struct Options {
     /// `-n`
     pub no_enumerate: bool,
 
+    /// `-o` (and `-J`)
+    pub output_format: OutputFormat,
+
     /// `-P`
     pub parallel: bool,
 
@@ -131,8 +205,14 @@ pub struct Options {
     /// `-v`
     pub verbose: bool,
 
+    /// `-w`
+    pub workers: Option<usize>,
+
     /// `-x`
     pub match_commands: Vec<String>,
+
+    /// `-Z`
+    pub no_decompress: bool,
 }
 
 /// The default input record delimiter. This pattern matches 1
@@ -158,19 +238,25 @@ impl Options {
     pub fn with_defaults() -> Result<Self, regex::Error> {
         Ok(Self {
             show_all: false,
+            buffer_size: None,
+            csv_input: false,
             fields: Vec::new(),
             depth: 0,
+            expressions: Vec::new(),
             print_empty: false,
             output_field_delimiter: Vec::from(DEFAULT_OUTPUT_FIELD_DELIMITER),
             input_field_delimiter: Regex::new(DEFAULT_INPUT_FIELD_DELIMITER)?,
+            header: None,
             help: false,
             invert_fields: false,
-            json_output: false,
             json_input: false,
+            kind: None,
+            ranges: Vec::new(),
             limit: None,
             mtime_expressions: Vec::new(),
             match_expressions: Vec::new(),
             no_enumerate: false,
+            output_format: OutputFormat::Columns,
             parallel: false,
             prune_expressions: Vec::new(),
             output_record_delimiter: Vec::from(DEFAULT_OUTPUT_RECORD_DELIMITER),
@@ -179,87 +265,280 @@ impl Options {
             skip: false,
             file_types: String::from(DEFAULT_FILE_TYPES),
             verbose: false,
+            workers: None,
             match_commands: Vec::new(),
+            no_decompress: false,
         })
     }
 }
 
-fn new_regex(pattern: &str, options: &Options) -> Result<Regex, regex::Error> {
+/// Returns a new `Regex` for `pattern`, which is case-insensitive unless `-S`
+/// was given or `pattern` contains an uppercase letter.
+pub fn new_regex(pattern: &str, options: &Options) -> Result<Regex, regex::Error> {
     RegexBuilder::new(pattern)
         .case_insensitive(!(options.case_sensitive || pattern.chars().any(char::is_uppercase)))
         .build()
 }
 
-/// Parses `arguments` according to `DEFAULT_OPTION_SPEC`. Returns the parsed
-/// `Options` and the remaining positional arguments. Any options not given on
-/// the command line will have their `DEFAULT_*` values in the returned
-/// `Options` (see `Options::with_defaults`).
-pub fn parse_options(arguments: &[String]) -> Result<(Options, &[String]), Box<dyn Error>> {
+/// Sets the option named by `option` (its short name) in `options`, using
+/// `value` if the option takes an argument.
+pub fn set_option(options: &mut Options, option: char, value: Option<&str>) -> EmptyResult {
+    match (option, value) {
+        ('a', None) => options.show_all = true,
+        ('b', Some(s)) => options.buffer_size = Some(str::parse::<usize>(s)?),
+        ('C', None) => options.csv_input = true,
+        ('c', Some(s)) => options.fields.push(s.to_string()),
+        ('d', Some(s)) => options.depth = str::parse::<usize>(s)?,
+        ('E', Some(s)) => options.expressions.push(Predicate::parse(s, options)?),
+        ('e', None) => options.print_empty = true,
+        ('F', Some(s)) => {
+            options.output_field_delimiter = Vec::from(unescape_backslashes(s)?.as_bytes());
+        }
+        ('f', Some(s)) => options.input_field_delimiter = new_regex(s, options)?,
+        ('I', None) => options.invert_fields = true,
+        ('H', Some(s)) => options.header = Some(s.parse()?),
+        ('h', None) => options.help = true,
+        ('J', None) => options.output_format = OutputFormat::Json,
+        ('j', None) => options.json_input = true,
+        ('k', Some(s)) => options.kind = Some(s.to_string()),
+        ('L', Some(s)) => options.ranges.push(s.parse()?),
+        ('l', Some(s)) => options.limit = Some(str::parse::<isize>(s)?),
+        ('M', Some(s)) => options.mtime_expressions.push(Time::new(s)?),
+        ('m', Some(s)) => options.match_expressions.push(new_regex(s, options)?),
+        ('n', None) => options.no_enumerate = true,
+        ('o', Some(s)) => options.output_format = s.parse()?,
+        ('P', None) => options.parallel = true,
+        ('p', Some(s)) => options.prune_expressions.push(new_regex(s, options)?),
+        ('R', Some(s)) => {
+            options.output_record_delimiter = Vec::from(unescape_backslashes(s)?.as_bytes());
+        }
+        ('r', Some(s)) => options.input_record_delimiter = new_regex(s, options)?,
+        ('S', None) => options.case_sensitive = true,
+        ('s', None) => options.skip = true,
+        ('t', Some(s)) => options.file_types = s.to_string(),
+        ('v', None) => options.verbose = true,
+        ('w', Some(s)) => {
+            options.workers = Some(str::parse::<usize>(s)?);
+            options.parallel = true;
+        }
+        ('x', Some(s)) => options.match_commands.push(s.to_string()),
+        ('Z', None) => options.no_decompress = true,
+        _ => return Err(UsageError::new("Unknown option").into()),
+    }
+    Ok(())
+}
+
+/// Returns `arguments[i]`, the argument to `option`, or an error if it is
+/// missing or not valid UTF-8.
+fn option_argument<'a>(
+    arguments: &'a [OsString],
+    i: usize,
+    option: &str,
+) -> Result<&'a str, UsageError> {
+    arguments
+        .get(i)
+        .ok_or_else(|| UsageError::new(&format!("option {option} requires an argument")))?
+        .to_str()
+        .ok_or_else(|| UsageError::new(&format!("argument to {option} not valid UTF-8")))
+}
+
+/// Returns the options that a program with the option specification `spec`
+/// accepts: their short names, long names, and whether they take an argument.
+///
+/// `spec` lists the short names of the options. A short name can be followed
+/// by a long name in parentheses, which replaces the one in `OPTION_SPECS` for
+/// that program. This is for programs that give an option a meaning that its
+/// usual long name does not describe; for example, `sort` calls `-I`
+/// `--reverse` rather than `--invert`.
+pub fn spec_options(spec: &str) -> Vec<(char, &str, bool)> {
+    let mut options = Vec::new();
+    let mut rest = spec;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        let mut long = None;
+        if let Some((name, after)) = rest.strip_prefix('(').and_then(|r| r.split_once(')')) {
+            long = Some(name);
+            rest = after;
+        }
+        if let Some(&(short, name, takes_argument)) = OPTION_SPECS.iter().find(|o| o.0 == c) {
+            options.push((short, long.unwrap_or(name), takes_argument));
+        }
+    }
+    options
+}
+
+/// Parses `arguments` (the first of which is the program name) according to
+/// `spec`, which lists the options the program accepts (see `spec_options`).
+/// Returns the parsed `Options` and the remaining positional arguments. Any
+/// options not given on the command line will have their configured values
+/// (see `config::configure`), or else their `DEFAULT_*` values (see
+/// `Options::with_defaults`).
+///
+/// Short options can be clustered (`-nS`), and an option’s argument can be
+/// attached (`-l5`) or separate (`-l 5`). Long options take their arguments
+/// as `--limit=5` or `--limit 5`. Parsing stops at `--` or at the first
+/// argument that is not an option, so that e.g. `mapx` can pass options to
+/// its command. Options the program does not accept are errors.
+///
+/// The options and their arguments must be valid UTF-8, but the positional
+/// arguments (typically pathnames) can be any bytes.
+pub fn parse_options<'a>(
+    arguments: &'a [OsString],
+    spec: &str,
+) -> Result<(Options, &'a [OsString]), Box<dyn Error>> {
+    let program = arguments
+        .first()
+        .and_then(|a| file_name(a))
+        .unwrap_or_default();
+    parse_program_options(program, arguments, spec)
+}
+
+/// Like `parse_options`, but uses the configuration for `program`, rather than
+/// for the program named by the first argument. This is for subcommands (such
+/// as `trash list`), whose first argument is the name of the subcommand.
+pub fn parse_program_options<'a>(
+    program: &str,
+    arguments: &'a [OsString],
+    spec: &str,
+) -> Result<(Options, &'a [OsString]), Box<dyn Error>> {
     let mut options = Options::with_defaults()?;
-    let mut parsed = getopt::Parser::new(arguments, DEFAULT_OPTION_SPEC);
-
-    loop {
-        match parsed.next().transpose()? {
-            None => break,
-            Some(opt) => match opt {
-                Opt('a', None) => options.show_all = true,
-                Opt('c', Some(s)) => options.fields.push(s.clone()),
-                Opt('d', Some(s)) => options.depth = str::parse::<usize>(&s)?,
-                Opt('e', None) => options.print_empty = true,
-                Opt('F', Some(s)) => {
-                    options.output_field_delimiter =
-                        Vec::from(unescape_backslashes(&s)?.as_bytes());
+    configure(&mut options, program, spec)?;
+    let accepted = spec_options(spec);
+    let find = |matches: &dyn Fn(&(char, &str, bool)) -> bool| accepted.iter().find(|o| matches(o));
+
+    let mut i = 1;
+    while i < arguments.len() {
+        let bytes = arguments[i].as_bytes();
+        if bytes == b"--" {
+            i += 1;
+            break;
+        }
+        if !bytes.starts_with(b"-") || bytes == b"-" {
+            break;
+        }
+        let Some(argument) = arguments[i].to_str() else {
+            return Err(UsageError::new(&format!(
+                "option not valid UTF-8: \"{}\"",
+                arguments[i].to_string_lossy()
+            ))
+            .into());
+        };
+
+        if let Some(long) = argument.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (long, None),
+            };
+            let Some(&(short, _, takes_argument)) = find(&|o| o.1 == name) else {
+                return Err(UsageError::new(&format!("invalid option: --{name}")).into());
+            };
+            let value = match (takes_argument, value) {
+                (true, Some(value)) => Some(value),
+                (true, None) => {
+                    i += 1;
+                    Some(option_argument(arguments, i, argument)?)
+                }
+                (false, None) => None,
+                (false, Some(_)) => {
+                    return Err(
+                        UsageError::new(&format!("option --{name} takes no argument")).into(),
+                    );
                 }
-                Opt('f', Some(s)) => options.input_field_delimiter = new_regex(&s, &options)?,
-                Opt('I', None) => options.invert_fields = true,
-                Opt('h', None) => options.help = true,
-                Opt('J', None) => options.json_output = true,
-                Opt('j', None) => options.json_input = true,
-                Opt('l', Some(s)) => options.limit = Some(str::parse::<isize>(&s)?),
-                Opt('M', Some(s)) => options.mtime_expressions.push(Time::new(&s)?),
-                Opt('m', Some(s)) => options.match_expressions.push(new_regex(&s, &options)?),
-                Opt('n', None) => options.no_enumerate = true,
-                Opt('P', None) => options.parallel = true,
-                Opt('p', Some(s)) => options.prune_expressions.push(new_regex(&s, &options)?),
-                Opt('R', Some(s)) => {
-                    options.output_record_delimiter =
-                        Vec::from(unescape_backslashes(&s)?.as_bytes());
+            };
+            set_option(&mut options, short, value)?;
+        } else {
+            for (j, c) in argument.char_indices().skip(1) {
+                let Some(&(short, _, takes_argument)) = find(&|o| o.0 == c) else {
+                    return Err(UsageError::new(&format!("invalid option: -{c}")).into());
+                };
+                if !takes_argument {
+                    set_option(&mut options, short, None)?;
+                    continue;
                 }
-                Opt('r', Some(s)) => options.input_record_delimiter = new_regex(&s, &options)?,
-                Opt('S', None) => options.case_sensitive = true,
-                Opt('s', None) => options.skip = true,
-                Opt('t', Some(s)) => options.file_types.clone_from(&s),
-                Opt('v', None) => options.verbose = true,
-                Opt('x', Some(s)) => options.match_commands.push(s.clone()),
-                Opt(_o, _) => return Err(UsageError::new("Unknown option").into()),
-            },
+                let rest = &argument[j + c.len_utf8()..];
+                let value = if rest.is_empty() {
+                    i += 1;
+                    option_argument(arguments, i, &format!("-{c}"))?
+                } else {
+                    rest
+                };
+                set_option(&mut options, short, Some(value))?;
+                break;
+            }
         }
+        i += 1;
     }
-    let (_, arguments) = arguments.split_at(parsed.index());
-    Ok((options, arguments))
+    Ok((options, &arguments[i..]))
 }
 
-pub static STDIN_PATHNAME: Lazy<String> = Lazy::new(|| "<stdin>".to_string());
+pub static STDIN_PATHNAME: LazyLock<&BStr> = LazyLock::new(|| BStr::new("<stdin>"));
 
 /// An open `Read`.
 pub struct OpenFile<'a> {
     /// The pathname by which the file was opened. If `None`, the file was
     /// already open (e.g. `stdin()`; see ).
-    pub pathname: Option<&'a String>,
+    pub pathname: Option<&'a BStr>,
     /// The `Read`.
     pub read: Result<Box<dyn Read>, io::Error>,
+    /// A memory mapping of the file, if it is a regular file and the
+    /// `FileOpener` is `mapped`. `read` is still valid.
+    pub map: Option<Mmap>,
 }
 
 /// An `Iterator` that iterates over a slice of pathnames, and yields
-/// `OpenFile`s.
+/// `OpenFile`s. Compressed files (and `stdin`) are decompressed as they are
+/// read, unless `options.no_decompress` is set; see `decompress::decompress`.
 pub struct FileOpener<'a> {
-    pathnames: &'a [String],
+    pathnames: &'a [OsString],
     i: usize,
+    decompress: bool,
+    map: bool,
 }
 
 impl<'a> FileOpener<'a> {
-    pub const fn new(pathnames: &'a [String]) -> Self {
-        FileOpener { pathnames, i: 0 }
+    pub const fn new(pathnames: &'a [OsString], options: &Options) -> Self {
+        FileOpener {
+            pathnames,
+            i: 0,
+            decompress: !options.no_decompress,
+            map: false,
+        }
+    }
+
+    /// Returns a `FileOpener` that also memory-maps regular files, so that
+    /// callers can read records from them without copying. (See
+    /// `file_records`.) Pipes and `stdin` are still streamed.
+    pub const fn mapped(mut self) -> Self {
+        self.map = true;
+        self
+    }
+
+    /// Returns a memory mapping of `file`, if `self.map` is set and `file` is
+    /// a non-empty regular file that is not compressed (or `self.decompress`
+    /// is not set). Otherwise, the caller should stream `file`.
+    fn mapping(&self, file: &File) -> Option<Mmap> {
+        let metadata = file.metadata().ok()?;
+        if !self.map || !metadata.is_file() || metadata.len() == 0 {
+            return None;
+        }
+        // SAFETY: If another process truncates the file while it is mapped,
+        // reading the missing pages raises `SIGBUS`. Like other tools that map
+        // their input, we accept that risk for the speed on large files such
+        // as logs.
+        let map = unsafe { Mmap::map(file) }.ok()?;
+        if self.decompress && is_compressed(&map) {
+            return None;
+        }
+        Some(map)
+    }
+
+    /// Returns `read`, decompressed if `self.decompress` is set.
+    fn open(&self, read: Box<dyn Read>) -> Result<Box<dyn Read>, io::Error> {
+        if self.decompress {
+            decompress(read)
+        } else {
+            Ok(read)
+        }
     }
 }
 
@@ -271,18 +550,23 @@ impl<'a> Iterator for FileOpener<'a> {
             self.i += 1;
             Some(OpenFile {
                 pathname: None,
-                read: Ok(Box::new(stdin()) as Box<dyn Read>),
+                read: self.open(Box::new(stdin())),
+                map: None,
             })
         } else if self.i < self.pathnames.len() {
             let pathname = &self.pathnames[self.i];
-            let r = match File::open(pathname) {
-                Ok(f) => Ok(Box::new(f) as Box<dyn Read>),
-                Err(e) => Err(e),
+            let (r, map) = match File::open(pathname) {
+                Ok(f) => match self.mapping(&f) {
+                    Some(map) => (Ok(Box::new(f) as Box<dyn Read>), Some(map)),
+                    None => (self.open(Box::new(f)), None),
+                },
+                Err(e) => (Err(e), None),
             };
             self.i += 1;
             Some(OpenFile {
-                pathname: Some(pathname),
+                pathname: Some(pathname.as_bytes().as_bstr()),
                 read: r,
+                map,
             })
         } else {
             None
@@ -290,8 +574,167 @@ impl<'a> Iterator for FileOpener<'a> {
     }
 }
 
-// Ultimately, this should go away and we should use a custom
-// `serde::ser::Serialize` for columnar output.
-pub trait StructuredWrite {
-    fn write(&self, output: &mut dyn Write, options: &Options) -> Result<(), io::Error>;
+/// An input's pathname and its `Read`, as `open_pair` returns them.
+pub type NamedInput<'a> = (&'a BStr, Box<dyn Read>);
+
+/// Opens the 2 inputs of a program that compares files, such as `common` and
+/// `join`: the 2 `pathnames`, or `stdin` and the pathname if there is only 1.
+/// Like `FileOpener`, decompresses them unless `options.no_decompress` is set.
+pub fn open_pair<'a>(
+    pathnames: &'a [OsString],
+    options: &Options,
+) -> Result<[NamedInput<'a>; 2], Box<dyn Error>> {
+    let stdin = if pathnames.len() == 1 {
+        FileOpener::new(&[], options).next()
+    } else {
+        None
+    };
+    let mut inputs = stdin
+        .into_iter()
+        .chain(FileOpener::new(pathnames, options))
+        .map(|file| {
+            let pathname = file.pathname.unwrap_or(&STDIN_PATHNAME);
+            file.read
+                .map(|read| (pathname, read))
+                .map_err(|error| format!("{pathname}: {error}"))
+        });
+    match (inputs.next(), inputs.next()) {
+        (Some(first), Some(second)) => Ok([first?, second?]),
+        _ => Err(UsageError::new("expected 1 or 2 pathnames").into()),
+    }
+}
+
+/// Returns an `Iterator` over the records in `read`, as `file_records` reads
+/// them but as whole records only.
+pub fn read_records<'a>(
+    read: &'a mut dyn Read,
+    pathname: &'a BStr,
+    options: &'a Options,
+) -> impl Iterator<Item = Vec<u8>> + 'a {
+    file_records(None, read, pathname, options).map(|record| record.r.into_owned())
+}
+
+/// Returns an `Iterator` over the records in `bytes`, split with `delimiter`,
+/// without copying them. Like `RegexSplitter`, yields no empty record after a
+/// final delimiter.
+pub fn split_records<'a>(bytes: &'a [u8], delimiter: &Regex) -> impl Iterator<Item = &'a [u8]> {
+    let mut records = delimiter.split(bytes).peekable();
+    iter::from_fn(move || {
+        let r = records.next()?;
+        if r.is_empty() && records.peek().is_none() {
+            None
+        } else {
+            Some(r)
+        }
+    })
+}
+
+/// Returns true if records are split with `options.input_record_delimiter`,
+/// rather than parsed from JSON or CSV.
+pub const fn splits_records(options: &Options) -> bool {
+    !options.json_input && !options.csv_input
+}
+
+/// A record, as `file_records` reads it.
+pub struct Record<'a> {
+    /// The whole record. If the input format delimits fields (JSON or CSV),
+    /// they are joined with `options.output_field_delimiter`.
+    pub r: Cow<'a, [u8]>,
+    /// The fields, if the input format delimits them, so that programs that
+    /// print fields need not split `r` (which may contain the delimiters) to
+    /// get them back.
+    pub fields: Option<Vec<Vec<u8>>>,
+}
+
+impl<'a> Record<'a> {
+    pub const fn whole(r: Cow<'a, [u8]>) -> Self {
+        Self { r, fields: None }
+    }
+
+    fn from_fields(fields: Vec<Vec<u8>>, options: &Options) -> Self {
+        Self {
+            r: Cow::Owned(fields.join(options.output_field_delimiter.as_slice())),
+            fields: Some(fields),
+        }
+    }
+
+    fn from_json(record: JsonRecord, options: &Options) -> Self {
+        match record {
+            JsonRecord::Record(r) => Self::whole(Cow::Owned(r)),
+            JsonRecord::Fields(fields) => Self::from_fields(fields, options),
+        }
+    }
+}
+
+type RecordResult<'a> = Result<Record<'a>, Box<dyn Error>>;
+
+/// An `Iterator` over the `Record`s in a file; see `file_records`. Stops at
+/// the first JSON or CSV parse error, printing it (prefixed with the pathname)
+/// to `stderr`, and counting it in `errors`.
+pub struct Records<'a> {
+    parsed: Box<dyn Iterator<Item = RecordResult<'a>> + 'a>,
+    pathname: &'a BStr,
+    /// The number of parse errors: 0 or 1.
+    pub errors: i32,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.errors > 0 {
+            return None;
+        }
+        match self.parsed.next()? {
+            Ok(record) => Some(record),
+            Err(error) => {
+                eprintln!("{}: {error}", self.pathname);
+                self.errors += 1;
+                None
+            }
+        }
+    }
+}
+
+/// Returns an `Iterator` over the records in an `OpenFile`. If
+/// `options.json_input` is set, parses `read` as JSON (see
+/// `json::JsonRecords`), and if `options.csv_input` is set, parses `read` as
+/// CSV (see `csv_records::csv_records`); either way, keeps the fields of each
+/// record as they were delimited in the input. Otherwise, splits the records
+/// with `options.input_record_delimiter`: if the file is memory-mapped (`map`;
+/// see `FileOpener::mapped`), the records are slices of the map, and if not,
+/// they are read from `read`.
+pub fn file_records<'a>(
+    map: Option<&'a [u8]>,
+    read: &'a mut dyn Read,
+    pathname: &'a BStr,
+    options: &'a Options,
+) -> Records<'a> {
+    let records: Box<dyn Iterator<Item = RecordResult<'a>> + 'a> = if options.json_input {
+        Box::new(
+            JsonRecords::new(read)
+                .map(move |r| -> RecordResult<'a> { Ok(Record::from_json(r?, options)) }),
+        )
+    } else if options.csv_input {
+        Box::new(
+            csv_records(read)
+                .map(move |r| -> RecordResult<'a> { Ok(Record::from_fields(r?, options)) }),
+        )
+    } else if let Some(map) = map {
+        Box::new(
+            split_records(map, &options.input_record_delimiter)
+                .map(|r| -> RecordResult<'a> { Ok(Record::whole(Cow::Borrowed(r))) }),
+        )
+    } else {
+        Box::new(
+            RegexSplitter::new(read, &options.input_record_delimiter)
+                .map_while(Result::ok)
+                .map(|r| -> RecordResult<'a> { Ok(Record::whole(Cow::Owned(r))) }),
+        )
+    };
+    Records {
+        parsed: records,
+        pathname,
+        errors: 0,
+    }
 }
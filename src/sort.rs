@@ -0,0 +1,383 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `futils sort` command.
+
+use crate::{
+    fields::{parse_fields, select_fields},
+    output::{StructuredWrite, StructuredWriter, split_record},
+    shell::{
+        FileOpener, Options, STDIN_PATHNAME, ShellResult, UsageError, parse_options, read_records,
+    },
+    time::parse_date_time,
+    util::{StrOrBytes, exit_with_result, help, icmp, natural_cmp, parse_number},
+};
+use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::BinaryHeap,
+    ffi::OsString,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write, stdout},
+    iter::zip,
+    str,
+};
+
+pub const SORT_HELP: &str = include_str!("sort.md");
+pub const SORT_HELP_VERBOSE: &str = include_str!("sort_verbose.md");
+
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "bceFfhI(reverse)Jjk(compare)oRrSvZ";
+
+/// How to compare keys.
+#[derive(Clone, Copy)]
+enum Kind {
+    Lexical,
+    Numeric,
+    Natural,
+    Time,
+}
+
+impl Kind {
+    fn new(kind: Option<&str>) -> Result<Self, UsageError> {
+        match kind {
+            None | Some("lexical") => Ok(Self::Lexical),
+            Some("numeric") => Ok(Self::Numeric),
+            Some("natural") => Ok(Self::Natural),
+            Some("time") => Ok(Self::Time),
+            Some(_) => Err(UsageError::new(
+                "sort kind must be 1 of lexical, numeric, natural, or time",
+            )),
+        }
+    }
+}
+
+/// A sort key, parsed from a field according to the `Kind`. Numbers and times
+/// that fail to parse are `None`, and sort before all others (even with `-I`).
+enum Key {
+    Bytes(Vec<u8>),
+    Number(Option<BigDecimal>),
+    Time(Option<NaiveDateTime>),
+}
+
+/// A record, along with its parsed sort keys.
+struct Sortable {
+    keys: Vec<Key>,
+    record: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct Sorted<'a> {
+    r: StrOrBytes<'a>,
+}
+
+impl StructuredWrite for Sorted<'_> {
+    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> Result<(), io::Error> {
+        output.write_all(self.r.0)?;
+        output.write_all(&options.output_record_delimiter)
+    }
+
+    fn to_fields(&self, options: &Options) -> Vec<Cow<'_, [u8]>> {
+        split_record(self.r.0, options)
+    }
+}
+
+struct Sorter<'a> {
+    /// The key fields, in order of precedence. If empty, the whole record is
+    /// the key.
+    fields: Vec<isize>,
+    kind: Kind,
+    options: &'a Options,
+}
+
+impl Sorter<'_> {
+    fn key(&self, field: &[u8]) -> Key {
+        match self.kind {
+            Kind::Lexical | Kind::Natural => Key::Bytes(field.to_vec()),
+            Kind::Numeric => Key::Number(parse_number(field).ok()),
+            Kind::Time => Key::Time(
+                str::from_utf8(field)
+                    .ok()
+                    .and_then(|f| parse_date_time(f.trim()).ok()),
+            ),
+        }
+    }
+
+    fn sortable(&self, record: Vec<u8>) -> Sortable {
+        let keys = if self.fields.is_empty() {
+            vec![self.key(&record)]
+        } else {
+            let fields = self
+                .options
+                .input_field_delimiter
+                .split(&record)
+                .collect::<Vec<&[u8]>>();
+            self.fields
+                .iter()
+                .map(|f| {
+                    self.key(
+                        select_fields(&fields, &[*f], false)
+                            .first()
+                            .copied()
+                            .unwrap_or_default(),
+                    )
+                })
+                .collect()
+        };
+        Sortable { keys, record }
+    }
+
+    /// Returns `ordering`, reversed if the user asked for reverse order.
+    const fn order(&self, ordering: Ordering) -> Ordering {
+        if self.options.invert_fields {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    /// Compares the parsed keys `a` and `b`. Keys that failed to parse sort
+    /// first, regardless of `-I`.
+    fn compare_parsed<T: Ord>(&self, a: Option<&T>, b: Option<&T>) -> Ordering {
+        match (a, b) {
+            (Some(a), Some(b)) => self.order(a.cmp(b)),
+            _ => a.is_some().cmp(&b.is_some()),
+        }
+    }
+
+    fn compare_keys(&self, a: &Key, b: &Key) -> Ordering {
+        match (a, b) {
+            (Key::Bytes(a), Key::Bytes(b)) => self.order(match self.kind {
+                Kind::Natural => natural_cmp(a, b, self.options.case_sensitive),
+                _ if self.options.case_sensitive => a.cmp(b),
+                _ => icmp(a, b),
+            }),
+            (Key::Number(a), Key::Number(b)) => self.compare_parsed(a.as_ref(), b.as_ref()),
+            (Key::Time(a), Key::Time(b)) => self.compare_parsed(a.as_ref(), b.as_ref()),
+            _ => Ordering::Equal,
+        }
+    }
+
+    fn compare(&self, a: &Sortable, b: &Sortable) -> Ordering {
+        zip(&a.keys, &b.keys)
+            .map(|(a, b)| self.compare_keys(a, b))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+
+    /// Sorts `run` in place. The sort is stable.
+    fn sort(&self, run: &mut [Sortable]) {
+        run.sort_by(|a, b| self.compare(a, b));
+    }
+
+    /// Merges the sorted `runs`, which must be in input order, and prints the
+    /// records. Records with equal keys are printed in input order.
+    ///
+    /// So as not to run out of file descriptors, we merge at most
+    /// `MERGE_FAN_IN` runs at a time, in as many passes as it takes.
+    fn merge(&self, mut runs: Vec<File>, output: &mut StructuredWriter) -> Result<(), io::Error> {
+        while runs.len() > MERGE_FAN_IN {
+            let mut merged = Vec::with_capacity(runs.len().div_ceil(MERGE_FAN_IN));
+            while !runs.is_empty() {
+                // Merging consecutive runs keeps the merged runs in input order.
+                let group = runs
+                    .drain(..MERGE_FAN_IN.min(runs.len()))
+                    .collect::<Vec<_>>();
+                merged.push(new_run(|write| {
+                    self.merge_runs(group, &mut |s| write_run_record(write, &s.record))
+                })?);
+            }
+            runs = merged;
+        }
+        self.merge_runs(runs, &mut |s| {
+            output.write(&Sorted {
+                r: StrOrBytes(&s.record),
+            })
+        })
+    }
+
+    /// Merges the sorted `runs`, which must be in input order, calling `write`
+    /// for each record in order.
+    fn merge_runs(
+        &self,
+        runs: Vec<File>,
+        write: &mut dyn FnMut(&Sortable) -> Result<(), io::Error>,
+    ) -> Result<(), io::Error> {
+        let mut readers = runs.into_iter().map(BufReader::new).collect::<Vec<_>>();
+        let mut heads = BinaryHeap::with_capacity(readers.len());
+        for (run, reader) in readers.iter_mut().enumerate() {
+            if let Some(record) = read_run_record(reader)? {
+                heads.push(Head {
+                    sortable: self.sortable(record),
+                    run,
+                    sorter: self,
+                });
+            }
+        }
+        while let Some(head) = heads.pop() {
+            write(&head.sortable)?;
+            if let Some(record) = read_run_record(&mut readers[head.run])? {
+                heads.push(Head {
+                    sortable: self.sortable(record),
+                    ..head
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The most runs that `Sorter::merge` reads at once.
+const MERGE_FAN_IN: usize = 64;
+
+/// The next record of a run, while merging. `Head`s are ordered so that the
+/// `BinaryHeap` (a max-heap) pops the least record first, and among equal
+/// records, the one from the earliest run; that keeps the merge stable.
+struct Head<'a, 'b> {
+    sortable: Sortable,
+    run: usize,
+    sorter: &'a Sorter<'b>,
+}
+
+impl Ord for Head<'_, '_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sorter
+            .compare(&self.sortable, &other.sortable)
+            .then(self.run.cmp(&other.run))
+            .reverse()
+    }
+}
+
+impl PartialOrd for Head<'_, '_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head<'_, '_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Head<'_, '_> {}
+
+/// Creates a new temporary file, calls `fill` to write a run to it, and returns
+/// it rewound to the start.
+fn new_run(fill: impl FnOnce(&mut dyn Write) -> Result<(), io::Error>) -> Result<File, io::Error> {
+    let mut file = tempfile::tempfile()?;
+    let mut writer = BufWriter::new(&mut file);
+    fill(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+/// Writes `record` to a run, preceded by its length as a little-endian `u64`.
+fn write_run_record(write: &mut dyn Write, record: &[u8]) -> Result<(), io::Error> {
+    write.write_all(&(record.len() as u64).to_le_bytes())?;
+    write.write_all(record)
+}
+
+/// Writes the records of `run` to a new temporary file, and returns the file
+/// rewound to the start.
+fn write_run(run: &[Sortable]) -> Result<File, io::Error> {
+    new_run(|write| {
+        run.iter()
+            .try_for_each(|s| write_run_record(write, &s.record))
+    })
+}
+
+/// Reads the next record written by `write_run_record`, or `None` at the end
+/// of the run.
+fn read_run_record(read: &mut dyn Read) -> Result<Option<Vec<u8>>, io::Error> {
+    let mut length = [0; 8];
+    match read.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let length = usize::try_from(u64::from_le_bytes(length))
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    let mut record = vec![0; length];
+    read.read_exact(&mut record)?;
+    Ok(Some(record))
+}
+
+/// Runs the `sort` command on `arguments`.
+pub fn sort_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
+    if options.help {
+        exit_with_result(help(
+            0,
+            SORT_HELP,
+            true,
+            if options.verbose {
+                Some(SORT_HELP_VERBOSE)
+            } else {
+                None
+            },
+        ));
+    }
+
+    let sorter = Sorter {
+        fields: parse_fields(&options.fields)?,
+        kind: Kind::new(options.kind.as_deref())?,
+        options: &options,
+    };
+
+    // Records accumulate in `run` until it reaches `options.buffer_size`
+    // bytes; then we sort it and set it aside in a temporary file.
+    let mut status = 0;
+    let mut run = Vec::new();
+    let mut run_size = 0;
+    let mut runs = Vec::new();
+    for file in FileOpener::new(arguments, &options) {
+        let pathname = file.pathname.unwrap_or(&STDIN_PATHNAME);
+        match file.read {
+            Ok(mut read) => {
+                for record in read_records(&mut read, pathname, &options) {
+                    if record.is_empty() && !options.print_empty {
+                        continue;
+                    }
+                    run_size += record.len();
+                    run.push(sorter.sortable(record));
+                    if let Some(buffer_size) = options.buffer_size
+                        && run_size >= buffer_size
+                    {
+                        sorter.sort(&mut run);
+                        runs.push(write_run(&run)?);
+                        run.clear();
+                        run_size = 0;
+                    }
+                }
+            }
+            Err(error) => {
+                eprintln!("{pathname}: {error}");
+                status += 1;
+            }
+        }
+    }
+
+    sorter.sort(&mut run);
+    let mut output = StructuredWriter::new(stdout(), &options);
+    if runs.is_empty() {
+        for s in &run {
+            output.write(&Sorted {
+                r: StrOrBytes(&s.record),
+            })?;
+        }
+    } else {
+        if !run.is_empty() {
+            runs.push(write_run(&run)?);
+        }
+        drop(run);
+        sorter.merge(runs, &mut output)?;
+    }
+    output.finish()?;
+    Ok(status)
+}
@@ -5,16 +5,20 @@
 
 use crate::{
     os,
-    shell::{EmptyResult, Options, ShellResult, parse_options},
+    output::{StructuredWrite, StructuredWriter, number_field},
+    shell::{Options, ShellResult, parse_options},
     time::format_utc_timestamp,
     util::{exit_with_result, help},
 };
+use bstr::{BStr, ByteSlice};
 use libc::{getgrgid, getpwuid};
 use nix::sys::stat::{FileStat, Mode, lstat};
 use std::{
-    ffi::CStr,
+    borrow::Cow,
+    ffi::{CStr, OsString},
     fs::read_dir,
-    io::{IsTerminal, Write, stdout},
+    io::{self, Write, stdout},
+    os::unix::ffi::OsStrExt,
     path::Path,
     str,
 };
@@ -22,6 +26,9 @@ use std::{
 pub const STATUS_HELP: &str = include_str!("status.md");
 pub const STATUS_HELP_VERBOSE: &str = include_str!("status_verbose.md");
 
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "aFhJoRv";
+
 // TODO: Memoize this?
 fn user_name(uid: u32) -> Option<String> {
     let p = unsafe { getpwuid(uid) };
@@ -43,11 +50,11 @@ fn group_name(gid: u32) -> Option<String> {
 }
 
 fn format_uid(uid: u32) -> String {
-    user_name(uid).map_or_else(|| format!("{uid}"), |s| s)
+    user_name(uid).unwrap_or_else(|| format!("{uid}"))
 }
 
 fn format_gid(gid: u32) -> String {
-    group_name(gid).map_or_else(|| format!("{gid}"), |s| s)
+    group_name(gid).unwrap_or_else(|| format!("{gid}"))
 }
 
 const fn get_permissions(mode: os::Mode) -> Option<Mode> {
@@ -62,47 +69,50 @@ const fn get_permissions(mode: os::Mode) -> Option<Mode> {
 }
 
 fn format_permissions(mode: os::Mode) -> String {
-    get_permissions(mode).map_or("---------".to_string(), |mode| {
-        let mut bytes = vec![b'-'; 9];
-        if mode.contains(Mode::S_IRUSR) {
-            bytes[0] = b'r';
-        }
-        if mode.contains(Mode::S_IWUSR) {
-            bytes[1] = b'w';
-        }
-        if mode.contains(Mode::S_IXUSR) {
-            bytes[2] = b'x';
-        }
-        if mode.contains(Mode::S_ISUID) {
-            bytes[2] = b'S';
-        }
-        if mode.contains(Mode::S_IRGRP) {
-            bytes[3] = b'r';
-        }
-        if mode.contains(Mode::S_IWGRP) {
-            bytes[4] = b'w';
-        }
-        if mode.contains(Mode::S_IXGRP) {
-            bytes[5] = b'x';
-        }
-        if mode.contains(Mode::S_ISGID) {
-            bytes[5] = b'S';
-        }
-        if mode.contains(Mode::S_IROTH) {
-            bytes[6] = b'r';
-        }
-        if mode.contains(Mode::S_IWOTH) {
-            bytes[7] = b'w';
-        }
-        if mode.contains(Mode::S_IXOTH) {
-            bytes[8] = if mode.contains(Mode::S_ISVTX) {
-                b'T'
-            } else {
-                b'x'
+    get_permissions(mode).map_or_else(
+        || "---------".to_string(),
+        |mode| {
+            let mut bytes = vec![b'-'; 9];
+            if mode.contains(Mode::S_IRUSR) {
+                bytes[0] = b'r';
             }
-        }
-        String::from_utf8(bytes).map_or(String::new(), |v| v)
-    })
+            if mode.contains(Mode::S_IWUSR) {
+                bytes[1] = b'w';
+            }
+            if mode.contains(Mode::S_IXUSR) {
+                bytes[2] = b'x';
+            }
+            if mode.contains(Mode::S_ISUID) {
+                bytes[2] = b'S';
+            }
+            if mode.contains(Mode::S_IRGRP) {
+                bytes[3] = b'r';
+            }
+            if mode.contains(Mode::S_IWGRP) {
+                bytes[4] = b'w';
+            }
+            if mode.contains(Mode::S_IXGRP) {
+                bytes[5] = b'x';
+            }
+            if mode.contains(Mode::S_ISGID) {
+                bytes[5] = b'S';
+            }
+            if mode.contains(Mode::S_IROTH) {
+                bytes[6] = b'r';
+            }
+            if mode.contains(Mode::S_IWOTH) {
+                bytes[7] = b'w';
+            }
+            if mode.contains(Mode::S_IXOTH) {
+                bytes[8] = if mode.contains(Mode::S_ISVTX) {
+                    b'T'
+                } else {
+                    b'x'
+                }
+            }
+            String::from_utf8(bytes).map_or(String::new(), |v| v)
+        },
+    )
 }
 
 fn format_type(mode: os::Mode) -> String {
@@ -184,7 +194,7 @@ fn format_type(mode: os::Mode) -> String {
 }
 
 impl<'a> os::Status<'a> {
-    fn new(status: &FileStat, name: &'a str) -> Self {
+    fn new(status: &FileStat, name: &'a BStr) -> Self {
         os::Status {
             name,
             file_type: format_type(status.st_mode),
@@ -206,15 +216,11 @@ impl<'a> os::Status<'a> {
         }
     }
 
-    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> EmptyResult {
-        if options.verbose {
-            self.write_columns_verbose(output, &options.output_field_delimiter)
-        } else {
-            self.write_columns_concise(output, &options.output_field_delimiter)
-        }
-    }
-
-    fn write_columns_concise(&self, output: &mut dyn Write, field_delimiter: &[u8]) -> EmptyResult {
+    fn write_columns_concise(
+        &self,
+        output: &mut dyn Write,
+        field_delimiter: &[u8],
+    ) -> io::Result<()> {
         output.write_all(self.file_type.as_bytes())?;
         output.write_all(field_delimiter)?;
         output.write_all(self.permissions.as_bytes())?;
@@ -227,11 +233,15 @@ impl<'a> os::Status<'a> {
         output.write_all(field_delimiter)?;
         output.write_all(self.modified_time.as_bytes())?;
         output.write_all(field_delimiter)?;
-        output.write_all(self.name.as_bytes())?;
+        output.write_all(self.name)?;
         Ok(())
     }
 
-    fn write_columns_verbose(&self, output: &mut dyn Write, field_delimiter: &[u8]) -> EmptyResult {
+    fn write_columns_verbose(
+        &self,
+        output: &mut dyn Write,
+        field_delimiter: &[u8],
+    ) -> io::Result<()> {
         output.write_all(format!("{}", self.size).as_bytes())?;
         output.write_all(field_delimiter)?;
         output.write_all(self.modified_time.as_bytes())?;
@@ -265,23 +275,110 @@ impl<'a> os::Status<'a> {
         output.write_all(field_delimiter)?;
         output.write_all(format!("{}", self.block_size).as_bytes())?;
         output.write_all(field_delimiter)?;
-        output.write_all(self.name.as_bytes())?;
+        output.write_all(self.name)?;
         Ok(())
     }
+}
+
+/// The header row for `-v`.
+const VERBOSE_HEADERS: [&str; 16] = [
+    "Size",
+    "Modified",
+    "User",
+    "Group",
+    "Type",
+    "Permissions",
+    "Links",
+    "Device",
+    "Inode",
+    "Accessed",
+    "Changed",
+    "Birth",
+    "Mode",
+    "Blocks",
+    "Block Size",
+    "Name",
+];
 
-    fn write_json(&self, output: &mut dyn Write, pretty: bool) -> EmptyResult {
-        let to_json = if pretty {
-            serde_json::to_writer_pretty
+/// The header row, padded to line up with the columns format.
+const CONCISE_HEADERS: [&str; 7] = [
+    "Type",
+    "Permissions",
+    "User",
+    "Group",
+    "     Size",
+    "Modified           ",
+    "Name",
+];
+
+impl StructuredWrite for os::Status<'_> {
+    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> io::Result<()> {
+        if options.verbose {
+            self.write_columns_verbose(output, &options.output_field_delimiter)?;
+        } else {
+            self.write_columns_concise(output, &options.output_field_delimiter)?;
+        }
+        output.write_all(&options.output_record_delimiter)
+    }
+
+    fn to_fields(&self, options: &Options) -> Vec<Cow<'_, [u8]>> {
+        const fn text(s: &str) -> Cow<'_, [u8]> {
+            Cow::Borrowed(s.as_bytes())
+        }
+
+        if options.verbose {
+            let mut fields = vec![
+                number_field(self.size),
+                text(&self.modified_time),
+                text(&self.user),
+                text(&self.group),
+                text(&self.file_type),
+                text(&self.permissions),
+                number_field(self.links),
+                number_field(self.device),
+                number_field(self.inode),
+                text(&self.accessed_time),
+                text(&self.changed_time),
+            ];
+            #[cfg(target_os = "macos")]
+            fields.push(text(&self.birth_time));
+            fields.extend([
+                number_field(self.mode),
+                number_field(self.blocks),
+                number_field(self.block_size),
+                Cow::Borrowed(self.name.as_bytes()),
+            ]);
+            fields
+        } else {
+            vec![
+                text(&self.file_type),
+                text(&self.permissions),
+                text(&self.user),
+                text(&self.group),
+                number_field(self.size),
+                text(&self.modified_time),
+                Cow::Borrowed(self.name.as_bytes()),
+            ]
+        }
+    }
+
+    fn headers(&self, options: &Options) -> Vec<String> {
+        let headers = if options.verbose {
+            VERBOSE_HEADERS.as_slice()
         } else {
-            serde_json::to_writer
+            CONCISE_HEADERS.as_slice()
         };
-        Ok(to_json(output, &self)?)
+        headers
+            .iter()
+            .filter(|h| cfg!(target_os = "macos") || **h != "Birth")
+            .map(|h| h.trim().to_string())
+            .collect()
     }
 }
 
 /// Runs the `status` command on `arguments`.
-pub fn status_main(arguments: &[String]) -> ShellResult {
-    let (options, arguments) = parse_options(arguments)?;
+pub fn status_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
     if options.help {
         exit_with_result(help(
             0,
@@ -296,81 +393,35 @@ pub fn status_main(arguments: &[String]) -> ShellResult {
     }
 
     let arguments = if arguments.is_empty() {
-        // TODO: This crunchy code highlights that we have a type problem
-        // — we're doing a lot of work to turn `OsStr`s into `String`s, but
-        // ultimately we'll be changing CLI arguments to be always `OsString`.
-        // See also the conversion code at the top of `main`.
         read_dir(Path::new("."))?
-            .map(|entry| entry.unwrap().file_name().to_string_lossy().into())
-            .filter(|name: &String| options.show_all || !name.starts_with('.'))
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| options.show_all || !name.as_bytes().starts_with(b"."))
             .collect()
     } else {
         Vec::from(arguments)
     };
 
-    let mut stdout = stdout();
+    let mut output = StructuredWriter::new(stdout(), &options);
     let mut status = 0;
-    let count = arguments.len();
-    if options.json_output && count != 1 {
-        println!("[");
-    } else if !options.json_output {
-        let headers = if options.verbose {
-            vec![
-                b"Size".as_slice(),
-                b"Modified".as_slice(),
-                b"User".as_slice(),
-                b"Group".as_slice(),
-                b"Type".as_slice(),
-                b"Permissions".as_slice(),
-                b"Links".as_slice(),
-                b"Device".as_slice(),
-                b"Inode".as_slice(),
-                b"Accessed".as_slice(),
-                b"Changed".as_slice(),
-                b"Birth".as_slice(),
-                b"Mode".as_slice(),
-                b"Blocks".as_slice(),
-                b"Block Size".as_slice(),
-                b"Name".as_slice(),
-            ]
-        } else {
-            vec![
-                b"Type".as_slice(),
-                b"Permissions".as_slice(),
-                b"User".as_slice(),
-                b"Group".as_slice(),
-                b"     Size".as_slice(),
-                b"Modified           ".as_slice(),
-                b"Name".as_slice(),
-            ]
-        };
-        stdout.write_all(&headers.join(options.output_field_delimiter.as_slice()))?;
-        stdout.write_all(&options.output_record_delimiter)?;
-    }
-    for (i, pathname) in arguments.iter().enumerate() {
-        match lstat(pathname.as_str()) {
+    let headers = if options.verbose {
+        VERBOSE_HEADERS.as_slice()
+    } else {
+        CONCISE_HEADERS.as_slice()
+    };
+    let headers = headers.iter().map(|h| h.as_bytes()).collect::<Vec<_>>();
+    output.write_columns_only(&headers.join(options.output_field_delimiter.as_slice()))?;
+    output.write_columns_only(&options.output_record_delimiter)?;
+    for pathname in &arguments {
+        match lstat(pathname.as_os_str()) {
             Ok(s) => {
-                let s = os::Status::new(&s, pathname);
-                if options.json_output {
-                    let t = stdout.is_terminal();
-                    s.write_json(&mut stdout, t)?;
-                } else {
-                    s.write_columns(&mut stdout, &options)?;
-                }
-                stdout.write_all(if options.json_output {
-                    if i < count - 1 { b",\n" } else { b"\n" }
-                } else {
-                    &options.output_record_delimiter
-                })?;
+                output.write(&os::Status::new(&s, pathname.as_bytes().as_bstr()))?;
             }
             Err(error) => {
-                eprintln!("{pathname}: {error}");
+                eprintln!("{}: {error}", pathname.to_string_lossy());
                 status += 1;
             }
         }
     }
-    if options.json_output && count != 1 {
-        println!("]");
-    }
+    output.finish()?;
     Ok(status)
 }
@@ -0,0 +1,208 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! Aligned tables, for `-o table`.
+
+use std::io::{self, Write};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// The number of rows that `Table` buffers before printing them. The column
+/// widths come from the rows in the window (and any previous windows), so
+/// columns stay aligned without reading the whole input first.
+const WINDOW: usize = 100;
+
+/// The space between columns.
+const GUTTER: &str = "  ";
+
+/// Columns are not truncated to less than this width.
+const MIN_WIDTH: usize = 4;
+
+/// Returns `bytes` as text to put in a cell: invalid UTF-8 is replaced, and
+/// control characters (such as line breaks) become spaces.
+fn cell(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+/// Returns true if `s` looks like a number, so that its column is aligned to
+/// the right.
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.parse::<f64>().is_ok()
+}
+
+/// Returns `s`, truncated (with an ellipsis) to at most `width` columns wide,
+/// and its width.
+fn truncate(s: &str, width: usize) -> (String, usize) {
+    let w = s.width();
+    if w <= width {
+        return (s.to_string(), w);
+    }
+    let mut r = String::new();
+    let mut w = 0;
+    for c in s.chars() {
+        let cw = c.width().unwrap_or(0);
+        if w + cw + 1 > width {
+            break;
+        }
+        r.push(c);
+        w += cw;
+    }
+    r.push('…');
+    (r, w + 1)
+}
+
+/// Grows `widths` to fit `row`.
+fn measure(widths: &mut Vec<usize>, row: &[String]) {
+    if widths.len() < row.len() {
+        widths.resize(row.len(), 0);
+    }
+    for (w, c) in widths.iter_mut().zip(row) {
+        *w = (*w).max(c.width());
+    }
+}
+
+/// Rows of cells, printed with their columns aligned and fitted to a width.
+#[derive(Default)]
+pub struct Table {
+    headers: Option<Vec<String>>,
+    rows: Vec<Vec<String>>,
+    /// The natural (untruncated) width of each column so far.
+    widths: Vec<usize>,
+    header_written: bool,
+}
+
+impl Table {
+    /// Returns true if the table has a full window of rows, and should be
+    /// written.
+    pub const fn is_full(&self) -> bool {
+        self.rows.len() >= WINDOW
+    }
+
+    /// Sets the header row, if it has not been set already. An empty `headers`
+    /// means no header row.
+    pub fn set_headers(&mut self, headers: Vec<String>) {
+        if self.headers.is_none() {
+            self.headers = Some(headers);
+        }
+    }
+
+    /// Adds a row of `fields`.
+    pub fn push(&mut self, fields: &[impl AsRef<[u8]>]) {
+        self.rows
+            .push(fields.iter().map(|f| cell(f.as_ref())).collect());
+    }
+
+    /// Returns the column widths, narrowing the widest columns until the row
+    /// fits in `width` (or the columns are all narrow).
+    fn fit(&self, width: usize) -> Vec<usize> {
+        let mut widths = self.widths.clone();
+        let total = |widths: &[usize]| {
+            widths.iter().sum::<usize>() + GUTTER.len() * widths.len().saturating_sub(1)
+        };
+        while total(&widths) > width {
+            let Some(widest) = widths
+                .iter_mut()
+                .filter(|w| **w > MIN_WIDTH)
+                .max_by_key(|w| **w)
+            else {
+                break;
+            };
+            *widest -= 1;
+        }
+        widths
+    }
+
+    /// Writes the header row (the first time), and the buffered rows, fitted to
+    /// `width` columns. Each row ends with `record_delimiter`.
+    pub fn write(
+        &mut self,
+        output: &mut dyn Write,
+        width: usize,
+        record_delimiter: &[u8],
+    ) -> io::Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let headers = self.headers.as_deref().unwrap_or_default();
+        if !self.header_written {
+            measure(&mut self.widths, headers);
+        }
+        for row in &self.rows {
+            measure(&mut self.widths, row);
+        }
+        let widths = self.fit(width);
+        let numeric: Vec<bool> = (0..widths.len())
+            .map(|i| {
+                self.rows
+                    .iter()
+                    .filter_map(|r| r.get(i))
+                    .filter(|c| !c.is_empty())
+                    .all(|c| is_number(c))
+            })
+            .collect();
+
+        let write_row = |output: &mut dyn Write, row: &[String], numeric: &[bool]| {
+            for (i, c) in row.iter().enumerate() {
+                if i > 0 {
+                    output.write_all(GUTTER.as_bytes())?;
+                }
+                let (c, w) = truncate(c, widths[i]);
+                let padding = " ".repeat(widths[i].saturating_sub(w));
+                if numeric[i] {
+                    write!(output, "{padding}{c}")?;
+                } else if i == row.len() - 1 {
+                    write!(output, "{c}")?;
+                } else {
+                    write!(output, "{c}{padding}")?;
+                }
+            }
+            output.write_all(record_delimiter)
+        };
+
+        if !self.header_written && !headers.is_empty() {
+            write_row(output, headers, &numeric)?;
+        }
+        self.header_written = true;
+        for row in &self.rows {
+            write_row(output, row, &numeric)?;
+        }
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(headers: &[&str], rows: &[&[&str]], width: usize) -> String {
+        let mut table = Table::default();
+        table.set_headers(headers.iter().map(ToString::to_string).collect());
+        for row in rows {
+            table.push(row);
+        }
+        let mut output = Vec::new();
+        table.write(&mut output, width, b"\n").unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_table() {
+        assert_eq!(
+            "Name        Size\ngoat           1\nbilly goat   200\n",
+            table(
+                &["Name", "Size"],
+                &[&["goat", "1"], &["billy goat", "200"]],
+                80
+            )
+        );
+        assert_eq!(
+            "Name  Size\n山羊     1\n",
+            table(&["Name", "Size"], &[&["山羊", "1"]], 80)
+        );
+        assert_eq!("1  mountain…\n", table(&[], &[&["1", "mountain goat"]], 12));
+        assert_eq!("", table(&["Name"], &[], 80));
+    }
+}
@@ -22,6 +22,7 @@ pub fn format_utc_timestamp(utc: i64) -> String {
 
 /// A comparison operation on a `NaiveDateTime`. This is essentially a curried
 /// function (or, rather, 1 of 3 curried functions) on `date_time`.
+#[derive(Clone)]
 pub struct Time {
     /// A date-time that another date-time will be compared to.
     pub date_time: NaiveDateTime,
@@ -64,57 +65,48 @@ impl Time {
         };
         let string = string.trim();
 
-        let time = Self::from_date_time_string(string, operator);
-        match time {
-            Ok(time) => Ok(time),
-            Err(_) => match Self::from_time_string(string, operator) {
-                Ok(time) => Ok(time),
-                Err(_) => Ok(Self::from_date_string(string, operator)?),
-            },
-        }
-    }
-
-    fn from_date_time_string(string: &str, operator: Ordering) -> Result<Self, ParseError> {
         Ok(Self {
-            date_time: NaiveDateTime::parse_from_str(string, "%Y-%m-%d %H:%M:%S")?,
+            date_time: parse_date_time(string)?,
             ordering: operator,
         })
     }
 
-    fn from_time_string(string: &str, operator: Ordering) -> Result<Self, ParseError> {
-        let now = Local::now();
-        let time = NaiveTime::parse_from_str(string, "%H:%M:%S");
-        match time {
-            Ok(time) => {
-                let date_time = NaiveDate::from_ymd_opt(now.year(), now.month(), now.day())
-                    .unwrap()
-                    .and_hms_opt(time.hour(), time.minute(), time.second())
-                    .unwrap();
-                Ok(Self {
-                    date_time,
-                    ordering: operator,
-                })
-            }
-            Err(error) => Err(error),
+    /// Returns true if `date_time` is at or after (for `>`), at or before (for
+    /// `<`), or equal to (for `=`) `self.date_time`.
+    pub fn matches(&self, date_time: &NaiveDateTime) -> bool {
+        match self.ordering {
+            Ordering::Greater => self.date_time <= *date_time,
+            Ordering::Less => self.date_time >= *date_time,
+            Ordering::Equal => self.date_time == *date_time,
         }
     }
+}
 
-    fn from_date_string(string: &str, operator: Ordering) -> Result<Self, ParseError> {
-        let date = NaiveDate::parse_from_str(string, "%Y-%m-%d");
-        match date {
-            Ok(date) => {
-                let date_time = NaiveDate::from_ymd_opt(date.year(), date.month(), date.day())
-                    .unwrap()
-                    .and_hms_opt(0, 0, 0)
-                    .unwrap();
-                Ok(Self {
-                    date_time,
-                    ordering: operator,
-                })
-            }
-            Err(e) => Err(e),
-        }
-    }
+/// Parses `string` as a date and time (`%Y-%m-%d %H:%M:%S`), a time on the
+/// current day (`%H:%M:%S`), or a date at midnight (`%Y-%m-%d`).
+pub fn parse_date_time(string: &str) -> Result<NaiveDateTime, ParseError> {
+    NaiveDateTime::parse_from_str(string, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| parse_time_today(string))
+        .or_else(|_| parse_date(string))
+}
+
+fn parse_time_today(string: &str) -> Result<NaiveDateTime, ParseError> {
+    let now = Local::now();
+    let time = NaiveTime::parse_from_str(string, "%H:%M:%S")?;
+    Ok(NaiveDate::from_ymd_opt(now.year(), now.month(), now.day())
+        .unwrap()
+        .and_hms_opt(time.hour(), time.minute(), time.second())
+        .unwrap())
+}
+
+fn parse_date(string: &str) -> Result<NaiveDateTime, ParseError> {
+    let date = NaiveDate::parse_from_str(string, "%Y-%m-%d")?;
+    Ok(
+        NaiveDate::from_ymd_opt(date.year(), date.month(), date.day())
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap(),
+    )
 }
 
 #[test]
@@ -0,0 +1,517 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `futils trash` command, which implements the [freedesktop.org Trash
+//! specification](https://specifications.freedesktop.org/trash-spec/latest/).
+
+use crate::{
+    os,
+    output::{StructuredWrite, StructuredWriter},
+    shell::{
+        Options, STDIN_PATHNAME, ShellResult, UsageError, parse_options, parse_program_options,
+        read_records,
+    },
+    util::{exit_with_result, help, serialize_str_or_bytes},
+};
+use chrono::{Local, NaiveDateTime};
+use nix::unistd::getuid;
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    env,
+    error::Error,
+    ffi::{OsStr, OsString},
+    fmt::Write as _,
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::{self, Write, stdin, stdout},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+    },
+    path::{self, Path, PathBuf},
+};
+
+pub const TRASH_HELP: &str = include_str!("trash.md");
+pub const TRASH_HELP_VERBOSE: &str = include_str!("trash_verbose.md");
+
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "FhJjM(trashed)moRrSv";
+
+const DELETION_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+const TRASH_INFO_EXTENSION: &str = "trashinfo";
+
+/// Percent-encodes `bytes` for the `Path` key of a `.trashinfo` file.
+fn percent_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len());
+    for b in bytes {
+        if b.is_ascii_alphanumeric() || b"-_.!~*'()/".contains(b) {
+            encoded.push(char::from(*b));
+        } else {
+            write!(encoded, "%{b:02X}").unwrap();
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(b) = s
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(b);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    decoded
+}
+
+/// A trash directory, which contains `files` and `info` directories.
+struct Trash {
+    root: PathBuf,
+    /// The directory that relative `Path`s in `.trashinfo` files are relative
+    /// to.
+    top: PathBuf,
+}
+
+impl Trash {
+    fn files(&self) -> PathBuf {
+        self.root.join("files")
+    }
+
+    fn info(&self) -> PathBuf {
+        self.root.join("info")
+    }
+
+    fn create(&self) -> Result<(), io::Error> {
+        let mut builder = DirBuilder::new();
+        builder.recursive(true).mode(0o700);
+        builder.create(self.files())?;
+        builder.create(self.info())
+    }
+
+    /// Creates a new `.trashinfo` file for `name`, whose name is unique in both
+    /// `info` and `files`. Returns the file and the name to use in `files`.
+    fn create_info_file(&self, name: &OsStr) -> Result<(File, OsString), io::Error> {
+        let mut n = 1;
+        loop {
+            let mut candidate = name.to_os_string();
+            if n > 1 {
+                candidate.push(format!(".{n}"));
+            }
+            n += 1;
+            if fs::symlink_metadata(self.files().join(&candidate)).is_ok() {
+                continue;
+            }
+            let mut info_name = candidate.clone();
+            info_name.push(".");
+            info_name.push(TRASH_INFO_EXTENSION);
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.info().join(&info_name))
+            {
+                Ok(file) => return Ok((file, candidate)),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Returns `$XDG_DATA_HOME/Trash`, or `$HOME/.local/share/Trash`.
+fn home_trash() -> Option<Trash> {
+    let data = env::var_os("XDG_DATA_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".local/share")))?;
+    Some(Trash {
+        root: data.join("Trash"),
+        top: data,
+    })
+}
+
+/// Returns the top directory (i.e. the mount point) of the filesystem that
+/// `path` is on.
+fn top_directory(path: &Path, device: u64) -> Result<PathBuf, io::Error> {
+    let mut top = path;
+    while let Some(parent) = top.parent() {
+        if fs::metadata(parent)?.dev() != device {
+            break;
+        }
+        top = parent;
+    }
+    Ok(top.to_path_buf())
+}
+
+/// Returns `$topdir/.Trash/$uid`, if the administrator has created a suitable
+/// `$topdir/.Trash`: a directory (not a symbolic link) with the sticky bit set.
+fn shared_trash(top: &Path) -> Option<Trash> {
+    let shared = top.join(".Trash");
+    let metadata = fs::symlink_metadata(&shared).ok()?;
+    (metadata.is_dir() && metadata.permissions().mode() & 0o1000 != 0).then(|| Trash {
+        root: shared.join(getuid().to_string()),
+        top: top.to_path_buf(),
+    })
+}
+
+/// Returns `$topdir/.Trash-$uid`.
+fn own_trash(top: &Path) -> Trash {
+    Trash {
+        root: top.join(format!(".Trash-{}", getuid())),
+        top: top.to_path_buf(),
+    }
+}
+
+/// Returns the trash directory for the absolute `path`: the home trash if
+/// `path` is on the same filesystem, or else the `shared_trash` or `own_trash`
+/// at the top of its filesystem.
+fn trash_for(path: &Path) -> Result<Trash, io::Error> {
+    let device = fs::symlink_metadata(path)?.dev();
+    if let Some(home) = home_trash() {
+        // The home trash might not exist yet.
+        let existing = home.root.ancestors().find(|a| a.exists());
+        if existing
+            .and_then(|a| fs::metadata(a).ok())
+            .is_some_and(|m| m.dev() == device)
+        {
+            return Ok(home);
+        }
+    }
+
+    let top = top_directory(path, device)?;
+    Ok(shared_trash(&top).unwrap_or_else(|| own_trash(&top)))
+}
+
+/// Returns true if the last component of `pathname` is `.` or `..`.
+/// (`path::absolute` and `Path::components` drop a trailing `.`, so this has to
+/// look at the bytes.)
+fn is_dot_or_dot_dot(pathname: &Path) -> bool {
+    let last = pathname
+        .as_os_str()
+        .as_bytes()
+        .rsplit(|&b| b == b'/')
+        .find(|c| !c.is_empty());
+    matches!(last, Some(b"." | b".."))
+}
+
+/// Moves `pathname` to the trash.
+fn trash(pathname: &Path) -> Result<(), Box<dyn Error>> {
+    // Like `rm`(1), refuse to trash `.` or `..`, which would take the current
+    // directory or its parent out from under the user.
+    if is_dot_or_dot_dot(pathname) {
+        return Err(UsageError::new("refusing to trash '.' or '..'").into());
+    }
+    let path = path::absolute(pathname)?;
+    let Some(name) = path.file_name() else {
+        return Err(UsageError::new("cannot trash a pathname with no file name").into());
+    };
+    let trash = trash_for(&path)?;
+    trash.create()?;
+    let (mut info, name) = trash.create_info_file(name)?;
+    let written = write!(
+        info,
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        percent_encode(path.as_os_str().as_bytes()),
+        Local::now().format(DELETION_DATE_FORMAT)
+    );
+    let moved = written.and_then(|()| fs::rename(&path, trash.files().join(&name)));
+    if let Err(error) = moved {
+        let mut info_name = name;
+        info_name.push(".");
+        info_name.push(TRASH_INFO_EXTENSION);
+        _ = fs::remove_file(trash.info().join(info_name));
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// A file in the trash, as described by its `.trashinfo` file.
+#[derive(Serialize)]
+struct Trashed {
+    deletion_date: String,
+    #[serde(serialize_with = "serialize_str_or_bytes")]
+    path: Vec<u8>,
+    #[serde(skip)]
+    file: PathBuf,
+    #[serde(skip)]
+    info: PathBuf,
+}
+
+impl Trashed {
+    fn read(trash: &Trash, info: PathBuf) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(&info)?;
+        let mut path = None;
+        let mut deletion_date = String::new();
+        for line in text.lines() {
+            if let Some(p) = line.strip_prefix("Path=") {
+                path = Some(percent_decode(p));
+            } else if let Some(d) = line.strip_prefix("DeletionDate=") {
+                deletion_date = d.to_string();
+            }
+        }
+        let Some(path) = path else {
+            return Err(UsageError::new("no Path in trash info file").into());
+        };
+        let path = trash.top.join(OsStr::from_bytes(&path));
+        let Some(name) = info.file_stem() else {
+            return Err(UsageError::new("invalid trash info file name").into());
+        };
+        Ok(Self {
+            deletion_date,
+            path: path.into_os_string().into_encoded_bytes(),
+            file: trash.files().join(name),
+            info,
+        })
+    }
+
+    fn path(&self) -> &Path {
+        Path::new(OsStr::from_bytes(&self.path))
+    }
+
+    fn matches(&self, options: &Options) -> bool {
+        options
+            .match_expressions
+            .iter()
+            .all(|re| re.is_match(&self.path))
+            && (options.mtime_expressions.is_empty()
+                || NaiveDateTime::parse_from_str(&self.deletion_date, DELETION_DATE_FORMAT)
+                    .is_ok_and(|d| options.mtime_expressions.iter().all(|t| t.matches(&d))))
+    }
+
+    /// Moves the file back to where it came from, unless something else is
+    /// there now.
+    fn restore(&self) -> Result<(), io::Error> {
+        let path = self.path();
+        match os::rename_noreplace(&self.file, path) {
+            // Not every filesystem can rename without replacing. `link` never
+            // replaces either, but it works only for regular files.
+            Err(error)
+                if matches!(
+                    error.raw_os_error(),
+                    Some(libc::EINVAL | libc::ENOSYS | libc::ENOTSUP)
+                ) && fs::symlink_metadata(&self.file)?.is_file() =>
+            {
+                fs::hard_link(&self.file, path)?;
+                fs::remove_file(&self.file)?;
+            }
+            result => result?,
+        }
+        fs::remove_file(&self.info)
+    }
+
+    /// Deletes the file permanently.
+    fn remove(&self) -> Result<(), io::Error> {
+        match fs::symlink_metadata(&self.file) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&self.file)?,
+            Ok(_) => fs::remove_file(&self.file)?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+        fs::remove_file(&self.info)
+    }
+}
+
+impl StructuredWrite for Trashed {
+    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> Result<(), io::Error> {
+        output.write_all(self.deletion_date.as_bytes())?;
+        output.write_all(&options.output_field_delimiter)?;
+        output.write_all(&self.path)?;
+        output.write_all(&options.output_record_delimiter)
+    }
+
+    fn to_fields(&self, _: &Options) -> Vec<Cow<'_, [u8]>> {
+        vec![
+            Cow::Borrowed(self.deletion_date.as_bytes()),
+            Cow::Borrowed(&self.path),
+        ]
+    }
+}
+
+/// Returns the trash directories that `list`, `restore`, and `empty` look in:
+/// the home trash, and the trashes at the top of each of the `mount_points`
+/// and of the filesystem of the current directory. Leaves out top directory
+/// trashes that don’t exist (or that the user can’t see), so that a filesystem
+/// the user can’t read is not an error.
+fn trashes(mount_points: Vec<PathBuf>) -> Vec<Trash> {
+    let mut tops = mount_points;
+    if let Ok(current) = env::current_dir()
+        && let Ok(metadata) = fs::metadata(&current)
+        && let Ok(top) = top_directory(&current, metadata.dev())
+    {
+        tops.push(top);
+    }
+
+    let mut trashes = Vec::new();
+    trashes.extend(home_trash());
+    for top in tops {
+        for trash in shared_trash(&top).into_iter().chain([own_trash(&top)]) {
+            if fs::symlink_metadata(trash.info()).is_ok_and(|m| m.is_dir())
+                && !trashes.iter().any(|t| t.root == trash.root)
+            {
+                trashes.push(trash);
+            }
+        }
+    }
+    trashes
+}
+
+/// Returns the files in all the `trashes` that match the `-m` and `-M`
+/// `options`, oldest first. Returns the number of errors, too.
+fn trashed(options: &Options) -> (Vec<Trashed>, i32) {
+    let mut status = 0;
+    let mount_points = os::mount_points().unwrap_or_else(|error| {
+        eprintln!("cannot list mounted filesystems: {error}");
+        status += 1;
+        Vec::new()
+    });
+    let mut trashed = Vec::new();
+    for trash in trashes(mount_points) {
+        let entries = match fs::read_dir(trash.info()) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => {
+                eprintln!("{}: {error}", trash.info().display());
+                status += 1;
+                continue;
+            }
+        };
+        for entry in entries {
+            let info = match entry {
+                Ok(entry) => entry.path(),
+                Err(error) => {
+                    eprintln!("{}: {error}", trash.info().display());
+                    status += 1;
+                    continue;
+                }
+            };
+            if info.extension() != Some(OsStr::new(TRASH_INFO_EXTENSION)) {
+                continue;
+            }
+            match Trashed::read(&trash, info.clone()) {
+                Ok(t) if t.matches(options) => trashed.push(t),
+                Ok(_) => {}
+                Err(error) => {
+                    eprintln!("{}: {error}", info.display());
+                    status += 1;
+                }
+            }
+        }
+    }
+    trashed.sort_by(|a, b| {
+        a.deletion_date
+            .cmp(&b.deletion_date)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    (trashed, status)
+}
+
+/// Runs 1 of the `list`, `restore`, or `empty` subcommands. The first of the
+/// `arguments` is the subcommand name.
+fn manage(subcommand: &str, arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_program_options("trash", arguments, OPTION_SPEC)?;
+    if options.help {
+        exit_with_result(help(
+            0,
+            TRASH_HELP,
+            true,
+            if options.verbose {
+                Some(TRASH_HELP_VERBOSE)
+            } else {
+                None
+            },
+        ));
+    }
+    if !arguments.is_empty() {
+        exit_with_result(help(-1, TRASH_HELP, false, None));
+    }
+
+    let (trashed, mut status) = trashed(&options);
+    let mut output = StructuredWriter::new(stdout(), &options);
+    for t in trashed {
+        let result: Result<(), Box<dyn Error>> = match subcommand {
+            "list" => output.write(&t).map_err(Into::into),
+            "restore" => t.restore().map_err(Into::into),
+            "empty" => t.remove().map_err(Into::into),
+            _ => unreachable!(),
+        };
+        if let Err(error) = result {
+            eprintln!("{}: {error}", t.path().display());
+            status += 1;
+        }
+    }
+    if subcommand == "list" {
+        output.finish()?;
+    }
+    Ok(status)
+}
+
+/// Runs the `trash` command on `arguments`.
+pub fn trash_main(arguments: &[OsString]) -> ShellResult {
+    if let Some(subcommand @ ("list" | "restore" | "empty")) =
+        arguments.get(1).and_then(|a| a.to_str())
+    {
+        return manage(subcommand, &arguments[1..]);
+    }
+
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
+    if options.help {
+        exit_with_result(help(
+            0,
+            TRASH_HELP,
+            true,
+            if options.verbose {
+                Some(TRASH_HELP_VERBOSE)
+            } else {
+                None
+            },
+        ));
+    }
+
+    let mut status = 0;
+    let mut report = |pathname: &Path| {
+        if let Err(error) = trash(pathname) {
+            eprintln!("{}: {error}", pathname.display());
+            status += 1;
+        }
+    };
+    if arguments.is_empty() {
+        let mut stdin = stdin();
+        for record in read_records(&mut stdin, &STDIN_PATHNAME, &options) {
+            if !record.is_empty() {
+                report(Path::new(OsStr::from_bytes(&record)));
+            }
+        }
+    } else {
+        for pathname in arguments {
+            report(Path::new(pathname));
+        }
+    }
+    Ok(status)
+}
+
+#[test]
+fn test_percent_encoding() {
+    let pathname = "/home/goat/my files/100%.txt";
+    let encoded = percent_encode(pathname.as_bytes());
+    assert_eq!("/home/goat/my%20files/100%25.txt", encoded);
+    assert_eq!(pathname.as_bytes(), percent_decode(&encoded));
+    assert_eq!(b"/bad%zz".as_slice(), percent_decode("/bad%zz"));
+}
+
+#[test]
+fn test_is_dot_or_dot_dot() {
+    for pathname in [".", "..", "./", "../", "goat/.", "goat/..", "/goat/./"] {
+        assert!(is_dot_or_dot_dot(Path::new(pathname)), "{pathname}");
+    }
+    for pathname in ["goat", ".goat", "..goat", "./goat", "../goat", "/", ""] {
+        assert!(!is_dot_or_dot_dot(Path::new(pathname)), "{pathname}");
+    }
+}
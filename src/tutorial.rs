@@ -0,0 +1,42 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `futils tutorial` command.
+
+use crate::{
+    shell::{ShellResult, parse_options},
+    util::{exit_with_result, help},
+};
+use std::ffi::OsString;
+
+pub const TUTORIAL_HELP: &str = include_str!("tutorial.md");
+
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "h";
+
+/// The names of the tutorial topics, and their texts.
+const TOPICS: &[(&str, &str)] = &[
+    ("walk-and-awk", include_str!("tutorial_walk_and_awk.md")),
+    (
+        "records-and-fields",
+        include_str!("tutorial_records_and_fields.md"),
+    ),
+    ("json", include_str!("tutorial_json.md")),
+];
+
+/// Runs the `tutorial` command on `arguments`.
+pub fn tutorial_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
+    if options.help {
+        exit_with_result(help(0, TUTORIAL_HELP, false, None));
+    }
+
+    match arguments {
+        [] => help(0, TUTORIAL_HELP, false, None),
+        [topic] => match TOPICS.iter().find(|(name, _)| topic == name) {
+            Some((_, text)) => help(0, text, false, None),
+            None => help(-1, TUTORIAL_HELP, false, None),
+        },
+        _ => help(-1, TUTORIAL_HELP, false, None),
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `futils uniq` and `futils count` commands.
+
+use crate::{
+    fields::{parse_fields, select_fields},
+    output::{StructuredWrite, StructuredWriter, number_field, split_record},
+    shell::{FileOpener, Options, STDIN_PATHNAME, ShellResult, parse_options, read_records},
+    util::{StrOrBytes, exit_with_result, help, icmp},
+};
+use bstr::ByteSlice;
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, hash_map::Entry},
+    ffi::OsString,
+    io::{self, Write, stdout},
+    iter::zip,
+};
+
+pub const UNIQ_HELP: &str = include_str!("uniq.md");
+pub const UNIQ_HELP_VERBOSE: &str = include_str!("uniq_verbose.md");
+
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "aceFfhJjoRrSvZ";
+
+#[derive(Serialize)]
+struct Counted<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<u64>,
+    r: StrOrBytes<'a>,
+}
+
+impl StructuredWrite for Counted<'_> {
+    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> Result<(), io::Error> {
+        if let Some(count) = self.count {
+            write!(output, "{count:>5}")?;
+            output.write_all(&options.output_field_delimiter)?;
+        }
+        output.write_all(self.r.0)?;
+        output.write_all(&options.output_record_delimiter)
+    }
+
+    fn to_fields(&self, options: &Options) -> Vec<Cow<'_, [u8]>> {
+        let mut fields = Vec::new();
+        if let Some(count) = self.count {
+            fields.push(number_field(count));
+        }
+        fields.extend(split_record(self.r.0, options));
+        fields
+    }
+}
+
+/// A record, the key fields it was compared by, and the number of times it
+/// (or a record with the same key) occurred.
+struct Group {
+    key: Vec<Vec<u8>>,
+    record: Vec<u8>,
+    count: u64,
+}
+
+/// Returns true if the keys `a` and `b` are equal.
+fn same(a: &[Vec<u8>], b: &[Vec<u8>], case_sensitive: bool) -> bool {
+    a.len() == b.len()
+        && zip(a, b).all(|(a, b)| {
+            if case_sensitive {
+                a == b
+            } else {
+                icmp(a, b).is_eq()
+            }
+        })
+}
+
+struct Uniq<'a> {
+    /// The key fields. If empty, the whole record is the key.
+    fields: Vec<isize>,
+    counting: bool,
+    options: &'a Options,
+    /// The current run of adjacent records with the same key.
+    current: Option<Group>,
+    /// With `-a`, the index in `groups` of each (normalized) key seen so far.
+    seen: HashMap<Vec<Vec<u8>>, usize>,
+    groups: Vec<Group>,
+}
+
+impl Uniq<'_> {
+    fn key(&self, record: &[u8]) -> Vec<Vec<u8>> {
+        if self.fields.is_empty() {
+            return vec![record.to_vec()];
+        }
+        let fields = self
+            .options
+            .input_field_delimiter
+            .split(record)
+            .collect::<Vec<&[u8]>>();
+        select_fields(&fields, &self.fields, false)
+            .into_iter()
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    fn print(&self, group: &Group, output: &mut StructuredWriter) -> Result<(), io::Error> {
+        output.write(&Counted {
+            count: self.counting.then_some(group.count),
+            r: StrOrBytes(&group.record),
+        })
+    }
+
+    /// Counts `record`, printing whatever output is ready.
+    fn add(&mut self, record: Vec<u8>, output: &mut StructuredWriter) -> Result<(), io::Error> {
+        let key = self.key(&record);
+        if self.options.show_all {
+            let key = if self.options.case_sensitive {
+                key
+            } else {
+                key.iter().map(|k| k.to_lowercase()).collect()
+            };
+            match self.seen.entry(key) {
+                Entry::Occupied(e) if self.counting => self.groups[*e.get()].count += 1,
+                Entry::Occupied(_) => {}
+                Entry::Vacant(e) => {
+                    e.insert(self.groups.len());
+                    let group = Group {
+                        key: Vec::new(),
+                        record,
+                        count: 1,
+                    };
+                    if self.counting {
+                        self.groups.push(group);
+                    } else {
+                        self.print(&group, output)?;
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        if let Some(current) = &mut self.current
+            && same(&current.key, &key, self.options.case_sensitive)
+        {
+            current.count += 1;
+            return Ok(());
+        }
+        let group = Group {
+            key,
+            record,
+            count: 1,
+        };
+        if let Some(previous) = self.current.replace(group) {
+            self.print(&previous, output)?;
+        }
+        Ok(())
+    }
+
+    /// Prints any output that is still pending at the end of input.
+    fn finish(&mut self, output: &mut StructuredWriter) -> Result<(), io::Error> {
+        if let Some(current) = self.current.take() {
+            self.print(&current, output)?;
+        }
+        if self.counting {
+            for group in &self.groups {
+                self.print(group, output)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn run(arguments: &[OsString], counting: bool) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
+    if options.help {
+        exit_with_result(help(
+            0,
+            UNIQ_HELP,
+            true,
+            if options.verbose {
+                Some(UNIQ_HELP_VERBOSE)
+            } else {
+                None
+            },
+        ));
+    }
+
+    let mut uniq = Uniq {
+        fields: parse_fields(&options.fields)?,
+        counting,
+        options: &options,
+        current: None,
+        seen: HashMap::new(),
+        groups: Vec::new(),
+    };
+    let mut output = StructuredWriter::new(stdout(), &options);
+    let mut status = 0;
+    for file in FileOpener::new(arguments, &options) {
+        let pathname = file.pathname.unwrap_or(&STDIN_PATHNAME);
+        match file.read {
+            Ok(mut read) => {
+                for record in read_records(&mut read, pathname, &options) {
+                    if options.print_empty || !record.is_empty() {
+                        uniq.add(record, &mut output)?;
+                    }
+                }
+            }
+            Err(error) => {
+                eprintln!("{pathname}: {error}");
+                status += 1;
+            }
+        }
+    }
+    uniq.finish(&mut output)?;
+    output.finish()?;
+    Ok(status)
+}
+
+/// Runs the `uniq` command on `arguments`.
+pub fn uniq_main(arguments: &[OsString]) -> ShellResult {
+    run(arguments, false)
+}
+
+/// Runs the `count` command on `arguments`. It’s the same as `uniq`, but
+/// prints how many times each record occurred.
+pub fn count_main(arguments: &[OsString]) -> ShellResult {
+    run(arguments, true)
+}
@@ -2,19 +2,24 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::shell::ShellResult;
+use bigdecimal::BigDecimal;
 use bstr::ByteSlice;
+use locale::Numeric;
 use rustc_lexer::unescape::{EscapeError, unescape_str};
-use serde::Serializer;
+use serde::{Serialize, Serializer};
 use std::{
     cmp::{Ordering, min},
     env,
     error::Error,
+    ffi::OsStr,
     fmt::{self, Debug, Display, Formatter},
-    io::{Write, stderr, stdout},
+    io::{self, Write, stderr, stdout},
     iter::zip,
+    os::unix::ffi::OsStrExt,
     path::Path,
-    process::{Command, exit},
-    str::{self},
+    process::{Child, Command, Output, Stdio, exit},
+    str::{self, FromStr},
+    sync::LazyLock,
 };
 use termimad::{Alignment, FmtText, MadSkin, terminal_size};
 
@@ -64,23 +69,36 @@ pub fn help(status: i32, message: &str, common: bool, verbose: Option<&str>) ->
     Ok(status)
 }
 
-/// Runs the shell command `command`, passing it `argument`. If `verbose` is
-/// true, will print any resulting `stdout`. Prints `stderr` unconditionally.
-// TODO: `arguments` should be `&[OsString]`.
-pub fn run_command(command: &str, arguments: &[&[u8]], verbose: bool) -> ShellResult {
-    let words = shell_words::split(command)?;
-    let arguments = arguments
-        .iter()
-        .map(|a| str::from_utf8(a))
-        .collect::<Result<Vec<&str>, str::Utf8Error>>()?;
+/// Returns a `Command` that runs the shell command `command`.
+fn new_command(command: &str) -> io::Result<Command> {
+    let words = shell_words::split(command)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let Some((program, words)) = words.split_first() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty command"));
+    };
+    let mut command = Command::new(program);
+    command.args(words);
+    Ok(command)
+}
 
-    let mut command = Command::new(&words[0]);
-    if words.len() > 1 {
-        command.args(&words[1..]);
-    }
-    command.args(arguments);
-    let output = command.output()?;
+/// Runs the shell command `command`, passing it `arguments`, and returns its
+/// `Output`. Does not print anything; see `print_output`. The `arguments` can
+/// be any bytes; they need not be UTF-8.
+pub fn command_output(command: &str, arguments: &[&[u8]]) -> io::Result<Output> {
+    new_command(command)?
+        .args(arguments.iter().map(|a| OsStr::from_bytes(a)))
+        .output()
+}
+
+/// Starts the shell command `command`, with its `stdin` piped from the
+/// returned `Child`. Its `stdout` and `stderr` are ours.
+pub fn spawn_with_stdin(command: &str) -> io::Result<Child> {
+    new_command(command)?.stdin(Stdio::piped()).spawn()
+}
 
+/// Prints the `stdout` of `output` if `verbose` is true, and its `stderr`
+/// unconditionally. Returns the command’s exit status.
+pub fn print_output(output: &Output, verbose: bool) -> ShellResult {
     if verbose && !output.stdout.is_empty() {
         stdout().write_all(&output.stdout)?;
     }
@@ -90,6 +108,44 @@ pub fn run_command(command: &str, arguments: &[&[u8]], verbose: bool) -> ShellRe
     Ok(output.status.code().unwrap_or(0))
 }
 
+/// Runs the shell command `command`, passing it `argument`. If `verbose` is
+/// true, will print any resulting `stdout`. Prints `stderr` unconditionally.
+pub fn run_command(command: &str, arguments: &[&[u8]], verbose: bool) -> ShellResult {
+    print_output(&command_output(command, arguments)?, verbose)
+}
+
+/// Parses `value` as a number, according to the thousands and decimal
+/// separators of the user’s locale. If the locale has no thousands separator
+/// (as in the `C` locale), `,` is assumed.
+pub fn parse_number(value: &[u8]) -> Result<BigDecimal, Box<dyn Error>> {
+    static SEPARATORS: LazyLock<(String, String)> = LazyLock::new(|| {
+        let (thousands, decimal) = Numeric::load_user_locale().map_or_else(
+            |_| (String::new(), String::new()),
+            |numeric| (numeric.thousands_sep, numeric.decimal_sep),
+        );
+        let decimal = if decimal.is_empty() {
+            String::from(".")
+        } else {
+            decimal
+        };
+        let thousands = if thousands.is_empty() && decimal != "," {
+            String::from(",")
+        } else {
+            thousands
+        };
+        (thousands, decimal)
+    });
+    let (thousands, decimal) = &*SEPARATORS;
+    let mut value = str::from_utf8(value)?.trim().to_string();
+    if !thousands.is_empty() {
+        value = value.replace(thousands, "");
+    }
+    if decimal != "." {
+        value = value.replace(decimal, ".");
+    }
+    Ok(BigDecimal::from_str(&value)?)
+}
+
 // `EscapeError` does not actually implement `Error`, so we have to do it
 // ourselves.
 pub struct MyEscapeError {
@@ -142,7 +198,7 @@ pub fn unescape_backslashes(input: &str) -> Result<String, MyEscapeError> {
 
 /// Returns the basename of `pathname`. (Rust calls this `file_name` instead of
 /// `basename`, so we do, too.)
-pub fn file_name(pathname: &str) -> Option<&str> {
+pub fn file_name(pathname: &OsStr) -> Option<&str> {
     Path::new(pathname).file_name()?.to_str()
 }
 
@@ -157,6 +213,57 @@ pub fn icmp(a: &[u8], b: &[u8]) -> Ordering {
     a.len().cmp(&b.len())
 }
 
+/// Splits `s` after its leading run of bytes that are (or are not, if `digits`
+/// is false) ASCII digits.
+fn split_run(s: &[u8], digits: bool) -> (&[u8], &[u8]) {
+    let end = s
+        .iter()
+        .position(|b| b.is_ascii_digit() != digits)
+        .unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/// Compares in “natural” order, in which runs of digits compare by their
+/// numeric value: e.g. `file9` sorts before `file10`. Runs of non-digits
+/// compare as with `icmp`, unless `case_sensitive` is true.
+pub fn natural_cmp(a: &[u8], b: &[u8], case_sensitive: bool) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (first_a, first_b) = match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(first_a), Some(first_b)) => (*first_a, *first_b),
+        };
+        let ordering = if first_a.is_ascii_digit() && first_b.is_ascii_digit() {
+            let (digits_a, rest_a) = split_run(a, true);
+            let (digits_b, rest_b) = split_run(b, true);
+            a = rest_a;
+            b = rest_b;
+            let trimmed_a = &digits_a[digits_a.iter().take_while(|d| **d == b'0').count()..];
+            let trimmed_b = &digits_b[digits_b.iter().take_while(|d| **d == b'0').count()..];
+            trimmed_a
+                .len()
+                .cmp(&trimmed_b.len())
+                .then_with(|| trimmed_a.cmp(trimmed_b))
+                .then_with(|| digits_a.len().cmp(&digits_b.len()))
+        } else {
+            let (run_a, rest_a) = split_run(a, first_a.is_ascii_digit());
+            let (run_b, rest_b) = split_run(b, first_b.is_ascii_digit());
+            a = rest_a;
+            b = rest_b;
+            if case_sensitive {
+                run_a.cmp(run_b)
+            } else {
+                icmp(run_a, run_b)
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
 /// Serializes `string` as a UTF-8 string if possible, or as an array of bytes
 /// otherwise.
 pub fn serialize_str_or_bytes<S>(string: &[u8], serializer: S) -> Result<S::Ok, S::Error>
@@ -169,6 +276,18 @@ where
     }
 }
 
+/// A byte string that serializes as with `serialize_str_or_bytes`.
+pub struct StrOrBytes<'a>(pub &'a [u8]);
+
+impl Serialize for StrOrBytes<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_str_or_bytes(self.0, serializer)
+    }
+}
+
 pub fn exit_with_result(result: ShellResult) {
     match result {
         Ok(status) => exit(status),
@@ -181,8 +300,8 @@ pub fn exit_with_result(result: ShellResult) {
 
 #[cfg(test)]
 mod tests {
-    use crate::util::{file_name, icmp, unescape_backslashes};
-    use std::cmp::Ordering;
+    use crate::util::{file_name, icmp, natural_cmp, unescape_backslashes};
+    use std::{cmp::Ordering, ffi::OsStr};
 
     #[test]
     fn test_unescape_backslashes() {
@@ -202,13 +321,16 @@ goat	",
 
     #[test]
     fn test_file_name() {
-        assert_eq!("test", file_name("test").unwrap());
-        assert_eq!("test", file_name("./test").unwrap());
-        assert_eq!("test", file_name("/leg/foot/../test").unwrap());
-        assert_eq!("test", file_name("twerb/twib/test noodle/test").unwrap());
+        assert_eq!("test", file_name(OsStr::new("test")).unwrap());
+        assert_eq!("test", file_name(OsStr::new("./test")).unwrap());
+        assert_eq!("test", file_name(OsStr::new("/leg/foot/../test")).unwrap());
+        assert_eq!(
+            "test",
+            file_name(OsStr::new("twerb/twib/test noodle/test")).unwrap()
+        );
         assert_eq!(
             "test.exe",
-            file_name("twerb/twib/test noodle/test.exe").unwrap()
+            file_name(OsStr::new("twerb/twib/test noodle/test.exe")).unwrap()
         );
     }
 
@@ -223,4 +345,19 @@ goat	",
         assert_eq!(Ordering::Greater, icmp(b"goat", b"boat"));
         assert_eq!(Ordering::Greater, icmp(b"goat", b"BOAT"));
     }
+
+    #[test]
+    fn test_natural_cmp() {
+        assert_eq!(Ordering::Equal, natural_cmp(b"goat10", b"Goat10", false));
+        assert_eq!(Ordering::Less, natural_cmp(b"goat9", b"goat10", false));
+        assert_eq!(Ordering::Greater, natural_cmp(b"goat9", b"Goat10", true));
+        assert_eq!(
+            Ordering::Greater,
+            natural_cmp(b"goat10b", b"goat10a", false)
+        );
+        assert_eq!(Ordering::Less, natural_cmp(b"goat", b"goat1", false));
+        assert_eq!(Ordering::Less, natural_cmp(b"2 goats", b"10 goats", false));
+        assert_eq!(Ordering::Less, natural_cmp(b"7", b"007", false));
+        assert_eq!(Ordering::Less, natural_cmp(b"1.9", b"1.10", false));
+    }
 }
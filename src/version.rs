@@ -4,15 +4,23 @@
 //! The `futils version` command.
 
 use crate::{
-    shell::{EmptyResult, ShellResult, parse_options},
+    output::{StructuredWrite, StructuredWriter},
+    shell::{Options, ShellResult, parse_options},
     util::{exit_with_result, help},
 };
 use serde::Serialize;
-use std::io::{IsTerminal, Write, stdout};
+use std::{
+    borrow::Cow,
+    ffi::OsString,
+    io::{self, Write, stdout},
+};
 
 pub const VERSION_HELP: &str = include_str!("version.md");
 pub const VERSION_HELP_VERBOSE: &str = include_str!("version_verbose.md");
 
+/// The options this command accepts. See `parse_options`.
+const OPTION_SPEC: &str = "FhJoRv";
+
 #[derive(Serialize)]
 struct Metadata {
     name: &'static str,
@@ -29,24 +37,11 @@ struct Metadata {
     crate_name: &'static str,
 }
 
-impl Metadata {
-    fn write_json(&self, output: &mut dyn Write, pretty: bool) -> EmptyResult {
-        let to_json = if pretty {
-            serde_json::to_writer_pretty
-        } else {
-            serde_json::to_writer
-        };
-        Ok(to_json(output, &self)?)
-    }
-
-    fn write_columns(
-        &self,
-        output: &mut dyn Write,
-        field_delimiter: &[u8],
-        record_delimiter: &[u8],
-        verbose: bool,
-    ) -> EmptyResult {
-        if verbose {
+impl StructuredWrite for Metadata {
+    fn write_columns(&self, output: &mut dyn Write, options: &Options) -> io::Result<()> {
+        let field_delimiter = &options.output_field_delimiter;
+        let record_delimiter = &options.output_record_delimiter;
+        if options.verbose {
             write!(output, "Name")?;
             output.write_all(field_delimiter)?;
             output.write_all(self.name.as_bytes())?;
@@ -112,6 +107,31 @@ impl Metadata {
         }
         Ok(())
     }
+
+    fn to_fields(&self, options: &Options) -> Vec<Cow<'_, [u8]>> {
+        let values = if options.verbose {
+            vec![
+                self.name,
+                self.description,
+                self.version,
+                self.version_major,
+                self.version_minor,
+                self.version_patch,
+                self.version_pre,
+                self.repository,
+                self.license,
+                self.authors,
+                self.binary_name,
+                self.crate_name,
+            ]
+        } else {
+            vec![self.version]
+        };
+        values
+            .into_iter()
+            .map(|v| Cow::Borrowed(v.as_bytes()))
+            .collect()
+    }
 }
 
 const METADATA: Metadata = Metadata {
@@ -129,8 +149,8 @@ const METADATA: Metadata = Metadata {
     crate_name: env!("CARGO_CRATE_NAME"),
 };
 
-pub fn version_main(arguments: &[String]) -> ShellResult {
-    let (options, arguments) = parse_options(arguments)?;
+pub fn version_main(arguments: &[OsString]) -> ShellResult {
+    let (options, arguments) = parse_options(arguments, OPTION_SPEC)?;
     if options.help || !arguments.is_empty() {
         exit_with_result(help(
             0,
@@ -144,17 +164,8 @@ pub fn version_main(arguments: &[String]) -> ShellResult {
         ));
     }
 
-    let mut stdout = stdout();
-    if options.json_output {
-        let t = stdout.is_terminal();
-        METADATA.write_json(&mut stdout, t)?;
-    } else {
-        METADATA.write_columns(
-            &mut stdout,
-            &options.output_field_delimiter,
-            &options.output_record_delimiter,
-            options.verbose,
-        )?;
-    }
+    let mut output = StructuredWriter::new(stdout(), &options);
+    output.write(&METADATA)?;
+    output.finish()?;
     Ok(0)
 }
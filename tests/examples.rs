@@ -0,0 +1,217 @@
+// Copyright 2024 by [Chris Palmer](https://noncombatant.org)
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs the examples in the help pages and the tutorial, so that they cannot
+//! rot.
+//!
+//! An example is a line in a fenced code block that starts with `$ `. The
+//! rest of the line is a shell command, and the lines after it (up to the next
+//! example or the end of the block) are its expected output. The commands run
+//! with the links that `make install` creates in the `PATH`, in a fresh copy
+//! of the test-data directory for each block, so that examples can create and
+//! change files.
+//!
+//! Every fenced code block must either start with an example, or be marked as
+//! not runnable with an info string: `text` for things that are not commands
+//! (such as usage synopses and file contents), or `not-run` for commands that
+//! depend on the state of the system or on other programs. That way, an
+//! example cannot go untested by accident.
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[cfg(test)]
+const FUTILS: &str = "target/debug/futils";
+
+#[cfg(test)]
+const BIN: &str = "target/examples-bin";
+
+#[cfg(test)]
+const DATA: &str = "target/examples-data";
+
+/// An empty `$XDG_CONFIG_HOME`, so that the user’s configuration does not
+/// change the output.
+#[cfg(test)]
+const CONFIG: &str = "target/examples-config";
+
+#[cfg(test)]
+struct Example {
+    pathname: String,
+    line: usize,
+    command: String,
+    expected: String,
+}
+
+/// Returns the examples in the Markdown `text`, which came from `pathname`,
+/// grouped by fenced code block. Panics if a block is neither runnable nor
+/// marked as not runnable.
+#[cfg(test)]
+fn parse_examples(pathname: &str, text: &str) -> Vec<Vec<Example>> {
+    let mut blocks: Vec<Vec<Example>> = Vec::new();
+    // The info string and the line number of the fence of the block we are in.
+    let mut block: Option<(&str, usize)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        if let Some(info) = line.strip_prefix("```") {
+            block = match block {
+                None => {
+                    match info {
+                        "" => blocks.push(Vec::new()),
+                        "text" | "not-run" => {}
+                        _ => panic!(
+                            "{pathname}:{line_number}: unknown block type `{info}` (use `text` or `not-run`)"
+                        ),
+                    }
+                    Some((info, line_number))
+                }
+                Some((info, start)) => {
+                    assert!(
+                        !info.is_empty() || blocks.last().is_some_and(|b| !b.is_empty()),
+                        "{pathname}:{start}: empty block (mark it `text` or `not-run`)"
+                    );
+                    None
+                }
+            };
+            continue;
+        }
+        let Some((info, start)) = block else {
+            continue;
+        };
+        let command = line.strip_prefix("$ ");
+        if !info.is_empty() {
+            assert!(
+                command.is_none(),
+                "{pathname}:{line_number}: example in a `{info}` block"
+            );
+            continue;
+        }
+        let examples = blocks.last_mut().unwrap();
+        if let Some(command) = command {
+            examples.push(Example {
+                pathname: pathname.to_string(),
+                line: line_number,
+                command: command.to_string(),
+                expected: String::new(),
+            });
+        } else if let Some(example) = examples.last_mut() {
+            example.expected.push_str(line);
+            example.expected.push('\n');
+        } else {
+            panic!(
+                "{pathname}:{start}: block does not start with a `$ ` example (if it should not run, mark it `text` or `not-run`)"
+            );
+        }
+    }
+    assert!(block.is_none(), "{pathname}: unterminated block");
+    blocks
+}
+
+/// Copies the directory tree `from` to `to`.
+#[cfg(test)]
+fn copy_tree(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let to = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_tree(&entry.path(), &to);
+        } else {
+            fs::copy(entry.path(), to).unwrap();
+        }
+    }
+}
+
+/// Returns the names of the links to `futils` that `make install` creates.
+#[cfg(test)]
+fn installed_names() -> Vec<String> {
+    let makefile = fs::read_to_string("Makefile").unwrap();
+    makefile
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("ln -f $(INSTALL_ROOT)/bin/futils "))
+        .map(|l| l.trim_start_matches("$(INSTALL_ROOT)/bin/").to_string())
+        .collect()
+}
+
+/// Creates a directory of links to `futils`, 1 for each name that `make
+/// install` installs (and 1 named `futils`), and returns its absolute
+/// pathname.
+#[cfg(test)]
+fn make_bin() -> PathBuf {
+    let futils = fs::canonicalize(FUTILS).unwrap();
+    let _ = fs::remove_dir_all(BIN);
+    fs::create_dir_all(BIN).unwrap();
+    let mut names = installed_names();
+    names.push(String::from("futils"));
+    for name in names {
+        symlink(&futils, Path::new(BIN).join(name)).unwrap();
+    }
+    fs::canonicalize(BIN).unwrap()
+}
+
+#[cfg(test)]
+fn run_examples(pathnames: &[PathBuf]) {
+    let bin = make_bin();
+    let _ = fs::remove_dir_all(CONFIG);
+    fs::create_dir_all(CONFIG).unwrap();
+    let config = fs::canonicalize(CONFIG).unwrap();
+    let path = match env::var_os("PATH") {
+        Some(path) => {
+            let mut paths = vec![bin];
+            paths.extend(env::split_paths(&path));
+            env::join_paths(paths).unwrap()
+        }
+        None => bin.into_os_string(),
+    };
+
+    let mut count = 0;
+    for pathname in pathnames {
+        let pathname = pathname.to_str().unwrap();
+        let text = fs::read_to_string(pathname).unwrap();
+        for block in parse_examples(pathname, &text) {
+            let _ = fs::remove_dir_all(DATA);
+            copy_tree(Path::new("test-data"), Path::new(DATA));
+            for example in block {
+                let output = Command::new("sh")
+                    .arg("-c")
+                    .arg(&example.command)
+                    .current_dir(DATA)
+                    .env("PATH", &path)
+                    .env("LC_ALL", "C")
+                    .env_remove("MANCOLOR")
+                    .env("XDG_CONFIG_HOME", &config)
+                    .output()
+                    .unwrap();
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                assert_eq!(
+                    example.expected, stdout,
+                    "{}:{}: {}",
+                    example.pathname, example.line, example.command
+                );
+                assert!(
+                    output.stderr.is_empty(),
+                    "{}:{}: {}: {}",
+                    example.pathname,
+                    example.line,
+                    example.command,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                count += 1;
+            }
+        }
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn test_documented_examples() {
+    let mut pathnames = fs::read_dir("src")
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "md"))
+        .collect::<Vec<PathBuf>>();
+    pathnames.sort();
+    run_examples(&pathnames);
+}
@@ -1,20 +1,56 @@
 // Copyright 2022 by [Chris Palmer](https://noncombatant.org)
 // SPDX-License-Identifier: Apache-2.0
 
-use std::process::Command;
-use std::str::from_utf8;
+use bstr::BStr;
+use std::{
+    ffi::OsStr,
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+    str::from_utf8,
+};
 
 #[cfg(test)]
 const FUTILS: &str = "target/debug/futils";
 
+/// A run of `futils program arguments`, and what it should print. The
+/// arguments and the expected output are `str`s unless a test needs bytes that
+/// are not UTF-8.
 #[cfg(test)]
-struct TestCase<'a> {
+struct TestCase<'a, A: ?Sized = str, E: ?Sized = str> {
     name: &'a str,
     program: &'a str,
-    arguments: &'a [&'a str],
-    expected: &'a str,
+    arguments: &'a [&'a A],
+    expected: &'a E,
     sorted: bool,
     expected_status: i32,
+    /// The program should print errors to `stderr`.
+    expected_errors: bool,
+    /// Variables to set in the program’s environment.
+    env: &'a [(&'a str, &'a str)],
+    /// The directory to run the program in, if not the current directory.
+    cwd: Option<&'a Path>,
+    /// The program’s `stdin`, if any.
+    stdin: Option<&'a [u8]>,
+}
+
+#[cfg(test)]
+impl Default for TestCase<'_> {
+    fn default() -> Self {
+        Self {
+            name: "",
+            program: "",
+            arguments: &[],
+            expected: "",
+            sorted: false,
+            expected_status: 0,
+            expected_errors: false,
+            env: &[],
+            cwd: None,
+            stdin: None,
+        }
+    }
 }
 
 fn sort_lines(v: &str) -> String {
@@ -27,37 +63,63 @@ fn sort_lines(v: &str) -> String {
 }
 
 #[cfg(test)]
-impl<'a> TestCase<'a> {
+impl<A: AsRef<OsStr> + ?Sized, E: AsRef<[u8]> + ?Sized> TestCase<'_, A, E> {
     fn run(&self) {
-        let output = Command::new(FUTILS)
+        // `FUTILS` is relative to this directory, not to `cwd`.
+        let mut command = Command::new(fs::canonicalize(FUTILS).unwrap());
+        command
             .arg(self.program)
             .args(self.arguments)
-            .output();
+            .envs(self.env.iter().copied());
+        if let Some(cwd) = self.cwd {
+            command.current_dir(cwd);
+        }
+        let output = match self.stdin {
+            Some(stdin) => command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .and_then(|mut child| {
+                    child.stdin.take().unwrap().write_all(stdin)?;
+                    child.wait_with_output()
+                }),
+            None => command.output(),
+        };
         match output {
             Ok(output) => {
-                let lines = from_utf8(&output.stdout).unwrap();
+                let expected = BStr::new(self.expected.as_ref());
+                let sorted;
                 let lines = if self.sorted {
-                    &sort_lines(lines)
+                    sorted = sort_lines(from_utf8(&output.stdout).unwrap());
+                    BStr::new(&sorted)
                 } else {
-                    lines
+                    BStr::new(&output.stdout)
                 };
-                if self.expected != lines {
-                    eprintln!("{}", self.name);
-                }
-                assert_eq!(self.expected, lines);
-                assert!(output.stderr.is_empty());
-                assert_eq!(self.expected_status, output.status.code().unwrap());
+                assert_eq!(expected, lines, "{}", self.name);
+                assert_eq!(
+                    self.expected_errors,
+                    !output.stderr.is_empty(),
+                    "{}: {}",
+                    self.name,
+                    BStr::new(&output.stderr)
+                );
+                assert_eq!(
+                    self.expected_status,
+                    output.status.code().unwrap(),
+                    "{}",
+                    self.name
+                );
             }
             Err(e) => {
-                eprintln!("{} {}", self.name, e);
-                assert!(false);
+                panic!("{} {}", self.name, e);
             }
         }
     }
 }
 
 #[cfg(test)]
-fn run_tests(cases: &[TestCase]) {
+fn run_tests<A: AsRef<OsStr> + ?Sized, E: AsRef<[u8]> + ?Sized>(cases: &[TestCase<A, E>]) {
     for c in cases {
         c.run()
     }
@@ -73,6 +135,7 @@ fn test_files_match_basic() {
             expected: "test-data/goat\n",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "files match basic simple case-insensitive",
@@ -82,6 +145,7 @@ fn test_files_match_basic() {
 test-data/goat",
             sorted: true,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "files match basic simple case-insensitive 2",
@@ -90,6 +154,17 @@ test-data/goat",
             expected: "test-data/Goats",
             sorted: true,
             expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "files expression",
+            program: "files",
+            arguments: &["-t", "f", "-E", "(m:common | m:cities) & !m:2", "test-data"],
+            expected: "test-data/cities.txt
+test-data/common1.txt",
+            sorted: true,
+            expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "files match multiple path parts",
@@ -98,6 +173,7 @@ test-data/goat",
             expected: "test-data/lurp/norp/yibb",
             sorted: true,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "files match depth 1",
@@ -105,15 +181,25 @@ test-data/goat",
             arguments: &["-d", "1", "test-data"],
             expected: "test-data
 test-data/Goats
+test-data/cities.txt
 test-data/columns.txt
 test-data/common1.txt
 test-data/common2.txt
+test-data/directories.txt
+test-data/farm-animals.json
 test-data/farm-animals.txt
 test-data/goat
 test-data/lurp
-test-data/numbers.txt",
+test-data/numbers.txt
+test-data/old-name.txt
+test-data/plan.txt
+test-data/renames.txt
+test-data/sales.tsv
+test-data/template.txt
+test-data/visits.txt",
             sorted: true,
             expected_status: 0,
+            ..Default::default()
         },
     ]);
 }
@@ -126,16 +212,26 @@ fn test_files_prune_basic() {
             program: "files",
             arguments: &["-p", "goat", "test-data"],
             expected: "test-data
+test-data/cities.txt
 test-data/columns.txt
 test-data/common1.txt
 test-data/common2.txt
+test-data/directories.txt
+test-data/farm-animals.json
 test-data/farm-animals.txt
 test-data/lurp
 test-data/lurp/norp
 test-data/lurp/norp/yibb
-test-data/numbers.txt",
+test-data/numbers.txt
+test-data/old-name.txt
+test-data/plan.txt
+test-data/renames.txt
+test-data/sales.tsv
+test-data/template.txt
+test-data/visits.txt",
             sorted: true,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "files prune basic simple case-sensitive",
@@ -143,31 +239,51 @@ test-data/numbers.txt",
             arguments: &["-S", "-p", "goat", "test-data"],
             expected: "test-data
 test-data/Goats
+test-data/cities.txt
 test-data/columns.txt
 test-data/common1.txt
 test-data/common2.txt
+test-data/directories.txt
+test-data/farm-animals.json
 test-data/farm-animals.txt
 test-data/lurp
 test-data/lurp/norp
 test-data/lurp/norp/yibb
-test-data/numbers.txt",
+test-data/numbers.txt
+test-data/old-name.txt
+test-data/plan.txt
+test-data/renames.txt
+test-data/sales.tsv
+test-data/template.txt
+test-data/visits.txt",
             sorted: true,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "files prune basic simple case-insensitive alternation",
             program: "files",
             arguments: &["-p", "(?i)(goat|yibb)", "test-data"],
             expected: "test-data
+test-data/cities.txt
 test-data/columns.txt
 test-data/common1.txt
 test-data/common2.txt
+test-data/directories.txt
+test-data/farm-animals.json
 test-data/farm-animals.txt
 test-data/lurp
 test-data/lurp/norp
-test-data/numbers.txt",
+test-data/numbers.txt
+test-data/old-name.txt
+test-data/plan.txt
+test-data/renames.txt
+test-data/sales.tsv
+test-data/template.txt
+test-data/visits.txt",
             sorted: true,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "files prune complex",
@@ -184,6 +300,35 @@ test-data/numbers.txt",
             expected: "test-data/columns.txt",
             sorted: true,
             expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "files prune expression",
+            program: "files",
+            arguments: &["-E", "p:norp$", "test-data/lurp"],
+            expected: "test-data/lurp",
+            sorted: true,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "files prune expression under or",
+            program: "files",
+            arguments: &["-t", "f", "-E", "p:norp$ | m:yibb", "test-data/lurp"],
+            expected: "test-data/lurp/norp/yibb",
+            sorted: true,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "files negated match expression",
+            program: "files",
+            arguments: &["-E", "!m:norp$", "test-data/lurp"],
+            expected: "test-data/lurp
+test-data/lurp/norp/yibb",
+            sorted: true,
+            expected_status: 0,
+            ..Default::default()
         },
     ]);
 }
@@ -200,6 +345,7 @@ test-data/columns.txt	    2	whee
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "fields 2 columns, non-enumerated",
@@ -210,6 +356,7 @@ whee	ouch
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "fields custom output delimiters",
@@ -218,6 +365,7 @@ whee	ouch
             expected: "yeahYwowYheyYfriendsXwheeYbonkYouchYboingX",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "fields all but column 2",
@@ -228,6 +376,7 @@ test-data/columns.txt	    2	whee	ouch	boing
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "fields negative columns, non-enumerated",
@@ -238,6 +387,50 @@ boing	ouch
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "fields ranges and lists",
+            program: "fields",
+            arguments: &["-n", "-c", "-1,1..3", "-c", "..1", "test-data/columns.txt"],
+            expected: "friends\twow\they\tyeah\nboing\tbonk\touch\twhee\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "fields inverted range",
+            program: "fields",
+            arguments: &["-n", "-I", "-c", "1..-1", "test-data/columns.txt"],
+            expected: "yeah\tfriends\nwhee\tboing\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "fields extract",
+            program: "fields",
+            arguments: &[
+                "-n",
+                "-m",
+                r"^([\d,]+)\t(?P<name>\w+)",
+                "-c",
+                "name",
+                "test-data/farm-animals.txt",
+            ],
+            expected: "mountain\nbilly\nsheep\nllamas\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "fields extract all",
+            program: "fields",
+            arguments: &["-n", "-a", "-m", "gr..s", "test-data/farm-animals.txt"],
+            expected: "grass\ngrass\ngrass\ngrass\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "fields inverted negative columns",
@@ -248,6 +441,7 @@ test-data/columns.txt	    2	whee	bonk
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "fields negative columns",
@@ -258,6 +452,7 @@ test-data/columns.txt	    2	boing	ouch
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
     ]);
 }
@@ -274,6 +469,37 @@ fn test_filter_basic() {
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "filter expression",
+            program: "filter",
+            arguments: &[
+                "-n",
+                "-E",
+                "!(m:goat | m:sheep)",
+                "test-data/farm-animals.txt",
+            ],
+            expected: "1,749	llamas	exclusively human flesh (for some reason)
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "filter expression with command",
+            program: "filter",
+            arguments: &[
+                "-n",
+                "-E",
+                "x:false | m:sheep",
+                "test-data/farm-animals.txt",
+            ],
+            expected: "12	sheep	grass, more grass
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "filter case-insensitive",
@@ -284,6 +510,7 @@ test-data/farm-animals.txt	    2	4	billy goats	grass, moss, vegetation, tin cans
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "filter non-enumerated case-insensitive",
@@ -294,6 +521,7 @@ test-data/farm-animals.txt	    2	4	billy goats	grass, moss, vegetation, tin cans
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "filter prune case-insensitive",
@@ -304,6 +532,7 @@ test-data/farm-animals.txt	    4	1,749	llamas	exclusively human flesh (for some
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "filter non-enumerated prune case-insensitive",
@@ -314,6 +543,7 @@ test-data/farm-animals.txt	    4	1,749	llamas	exclusively human flesh (for some
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "filter match capitals",
@@ -322,6 +552,7 @@ test-data/farm-animals.txt	    4	1,749	llamas	exclusively human flesh (for some
             expected: "",
             sorted: false,
             expected_status: 1,
+            ..Default::default()
         },
         TestCase {
             name: "filter match capitals, meaningless -i",
@@ -330,6 +561,7 @@ test-data/farm-animals.txt	    4	1,749	llamas	exclusively human flesh (for some
             expected: "",
             sorted: false,
             expected_status: 1,
+            ..Default::default()
         },
     ]);
 }
@@ -344,6 +576,7 @@ fn test_filter_limit0() {
             expected: "",
             sorted: false,
             expected_status: 1,
+            ..Default::default()
         },
         TestCase {
             name: "filter limit 0 prune",
@@ -352,6 +585,7 @@ fn test_filter_limit0() {
             expected: "",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "filter limit case-insensitive",
@@ -360,6 +594,78 @@ fn test_filter_limit0() {
             expected: "",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
+        },
+    ]);
+}
+
+#[test]
+fn test_parallel() {
+    run_tests(&[
+        TestCase {
+            name: "filter parallel commands",
+            program: "filter",
+            arguments: &["-w", "3", "-n", "-x", "true", "test-data/farm-animals.txt"],
+            expected: "1	mountain goat	grass, moss, vegetation
+4	billy goats	grass, moss, vegetation, tin cans
+12	sheep	grass, more grass
+1,749	llamas	exclusively human flesh (for some reason)
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "filter parallel failing commands",
+            program: "filter",
+            arguments: &["-P", "-x", "false", "test-data/farm-animals.txt"],
+            expected: "",
+            sorted: false,
+            expected_status: 1,
+            ..Default::default()
+        },
+        TestCase {
+            name: "map parallel output order",
+            program: "map",
+            arguments: &["-P", "-x", "echo", "test-data/common2.txt"],
+            expected: "Atlanta
+Boston
+cincinnati
+Detroit
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+    ]);
+}
+
+#[test]
+fn test_apply_basic() {
+    run_tests(&[
+        TestCase {
+            name: "apply fixed command",
+            program: "apply",
+            arguments: &["-x", "echo", "test-data/cities.txt"],
+            expected: "Atlanta Georgia
+Boston Massachusetts
+Detroit Michigan
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "apply fields as distinct arguments",
+            program: "apply",
+            arguments: &["-P", "-x", "printf '%s|%s\\n'", "test-data/cities.txt"],
+            expected: "Atlanta|Georgia
+Boston|Massachusetts
+Detroit|Michigan
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
         },
     ]);
 }
@@ -378,6 +684,7 @@ fn test_records_basic() {
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "records enumerated",
@@ -390,6 +697,7 @@ test-data/farm-animals.txt	    4	1,749	llamas	exclusively human flesh (for some
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "records limit",
@@ -400,10 +708,225 @@ test-data/farm-animals.txt	    2	4	billy goats	grass, moss, vegetation, tin cans
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
+        },
+    ]);
+}
+
+#[test]
+fn test_sort_unparsed_and_many_runs() {
+    use std::fmt::Write;
+
+    // With a tiny buffer, every record is its own run, so the merge takes more
+    // than 1 pass. It must still be stable.
+    let mut input = String::new();
+    let mut expected = String::new();
+    for i in 0..300 {
+        writeln!(input, "{}\t{i}", i % 7).unwrap();
+    }
+    for key in 0..7 {
+        for i in (key..300).step_by(7) {
+            writeln!(expected, "{key}\t{i}").unwrap();
+        }
+    }
+
+    run_tests(&[
+        // Keys that fail to parse sort first, in reverse order too.
+        TestCase {
+            name: "sort unparsed keys",
+            program: "sort",
+            arguments: &["-k", "numeric"],
+            expected: "goat\nsheep\n2\n10\n30\n",
+            stdin: Some(b"10\ngoat\n2\nsheep\n30\n"),
+            ..Default::default()
+        },
+        TestCase {
+            name: "sort unparsed keys reversed",
+            program: "sort",
+            arguments: &["-I", "-k", "numeric"],
+            expected: "goat\nsheep\n30\n10\n2\n",
+            stdin: Some(b"10\ngoat\n2\nsheep\n30\n"),
+            ..Default::default()
+        },
+        TestCase {
+            name: "sort many runs",
+            program: "sort",
+            arguments: &["-b", "1", "-k", "numeric", "-c", "0"],
+            expected: &expected,
+            stdin: Some(input.as_bytes()),
+            ..Default::default()
+        },
+    ]);
+}
+
+#[test]
+fn test_join_basic() {
+    run_tests(&[
+        TestCase {
+            name: "join inner",
+            program: "join",
+            arguments: &["test-data/common1.txt", "test-data/cities.txt"],
+            expected: "Atlanta	Georgia
+Boston	Massachusetts
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "join left",
+            program: "join",
+            arguments: &[
+                "-k",
+                "left",
+                "test-data/common1.txt",
+                "test-data/cities.txt",
+            ],
+            expected: "Atlanta	Georgia
+Boston	Massachusetts
+Cincinnati	
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "join right",
+            program: "join",
+            arguments: &[
+                "-k",
+                "right",
+                "test-data/common1.txt",
+                "test-data/cities.txt",
+            ],
+            expected: "Atlanta	Georgia
+Boston	Massachusetts
+Detroit	Michigan
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "join full, custom output delimiter",
+            program: "join",
+            arguments: &[
+                "-k",
+                "full",
+                "-F",
+                ",",
+                "test-data/common1.txt",
+                "test-data/cities.txt",
+            ],
+            expected: "Atlanta,Georgia
+Boston,Massachusetts
+Cincinnati,
+Detroit,Michigan
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "join JSON",
+            program: "join",
+            arguments: &[
+                "-J",
+                "-k",
+                "left",
+                "test-data/common1.txt",
+                "test-data/cities.txt",
+            ],
+            expected: r#"[
+{"key":"Atlanta","left":[],"right":["Georgia"]},
+{"key":"Boston","left":[],"right":["Massachusetts"]},
+{"key":"Cincinnati","left":[],"right":null}
+]
+"#,
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
         },
     ]);
 }
 
+#[test]
+fn test_join_ragged() {
+    // Unmatched records are padded to the width of the first record on the
+    // other side, not to the width of whichever record was read most recently.
+    let root = Path::new("target/test-join");
+    _ = fs::remove_dir_all(root);
+    fs::create_dir_all(root).unwrap();
+    fs::write(root.join("left.txt"), "a\t1\tx\nb\t2\nc\n").unwrap();
+    fs::write(root.join("right.txt"), "a\tA\nc\tC\tCC\nd\tD\n").unwrap();
+
+    run_tests(&[TestCase {
+        name: "join ragged",
+        program: "join",
+        arguments: &["-k", "full", "-F", ",", "left.txt", "right.txt"],
+        expected: "a,1,x,A\nb,2,\nc,C,CC\nd,,,D\n",
+        cwd: Some(root),
+        ..Default::default()
+    }]);
+}
+
+#[test]
+fn test_json_input() {
+    run_tests(&[
+        TestCase {
+            name: "records JSON input",
+            program: "records",
+            arguments: &["-j", "-n", "test-data/farm-animals.json"],
+            expected: "1	mountain goat	grass, moss, vegetation
+4	billy goats	grass, moss, vegetation, tin cans
+12	sheep	grass, more grass
+1,749	llamas	exclusively human flesh (for some reason)
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "fields JSON input",
+            program: "fields",
+            arguments: &["-j", "-n", "-c1", "test-data/farm-animals.json"],
+            expected: "mountain goat
+billy goats
+sheep
+llamas
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "filter JSON input",
+            program: "filter",
+            arguments: &["-j", "-m", "goat", "test-data/farm-animals.json"],
+            expected: "test-data/farm-animals.json	    1	1	mountain goat	grass, moss, vegetation
+test-data/farm-animals.json	    2	4	billy goats	grass, moss, vegetation, tin cans
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+    ]);
+
+    // Objects' members keep their order through a round trip.
+    let output = Command::new(FUTILS)
+        .args(["fields", "-H", "pass", "-J", "test-data/columns.txt"])
+        .output()
+        .unwrap();
+    run_tests(&[TestCase {
+        name: "fields JSON round trip",
+        program: "fields",
+        arguments: &["-j", "-n"],
+        expected: "whee\tbonk\touch\tboing\n",
+        stdin: Some(&output.stdout),
+        ..Default::default()
+    }]);
+}
+
 #[test]
 fn test_reduce_basic() {
     run_tests(&[
@@ -415,6 +938,7 @@ fn test_reduce_basic() {
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "reduce subtract",
@@ -424,6 +948,7 @@ fn test_reduce_basic() {
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "reduce multiply",
@@ -433,6 +958,7 @@ fn test_reduce_basic() {
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "reduce divide",
@@ -442,6 +968,258 @@ fn test_reduce_basic() {
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "reduce mean",
+            program: "reduce",
+            arguments: &["-x", "mean", "test-data/numbers.txt"],
+            expected: "420556.8
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "reduce column, multiple folds",
+            program: "reduce",
+            arguments: &[
+                "-c",
+                "0",
+                "-x",
+                "sum",
+                "-x",
+                "max",
+                "-x",
+                "count",
+                "test-data/farm-animals.txt",
+            ],
+            expected: "1766
+1749
+4
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "reduce all columns",
+            program: "reduce",
+            arguments: &["-a", "-x", "count", "test-data/farm-animals.txt"],
+            expected: "4	4	4
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "reduce command",
+            program: "reduce",
+            arguments: &["-x", "printf %s,%s", "test-data/common1.txt"],
+            expected: "Atlanta,Boston,Cincinnati
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "reduce NDJSON",
+            program: "reduce",
+            arguments: &["-o", "ndjson", "-x", "sum", "test-data/numbers.txt"],
+            expected: r#"{"x":"sum","values":["2102784"]}
+"#,
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+    ]);
+}
+
+#[test]
+fn test_sort_basic() {
+    run_tests(&[
+        TestCase {
+            name: "sort by field",
+            program: "sort",
+            arguments: &["-c", "1", "test-data/farm-animals.txt"],
+            expected: "4	billy goats	grass, moss, vegetation, tin cans
+1,749	llamas	exclusively human flesh (for some reason)
+1	mountain goat	grass, moss, vegetation
+12	sheep	grass, more grass
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "sort numeric reverse",
+            program: "sort",
+            arguments: &[
+                "-I",
+                "-c",
+                "0",
+                "-k",
+                "numeric",
+                "test-data/farm-animals.txt",
+            ],
+            expected: "1,749	llamas	exclusively human flesh (for some reason)
+12	sheep	grass, more grass
+4	billy goats	grass, moss, vegetation, tin cans
+1	mountain goat	grass, moss, vegetation
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "sort numeric stable",
+            program: "sort",
+            arguments: &["-k", "numeric", "test-data/numbers.txt"],
+            expected: "512
+1,024
+4,096
+1048576
+1,048,576
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "sort external merge",
+            program: "sort",
+            arguments: &["-b", "8", "-k", "numeric", "test-data/numbers.txt"],
+            expected: "512
+1,024
+4,096
+1048576
+1,048,576
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "sort case-insensitive",
+            program: "sort",
+            arguments: &["test-data/common2.txt"],
+            expected: "Atlanta
+Boston
+cincinnati
+Detroit
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "sort case-sensitive",
+            program: "sort",
+            arguments: &["-S", "test-data/common2.txt"],
+            expected: "Atlanta
+Boston
+Detroit
+cincinnati
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+    ]);
+}
+
+#[test]
+fn test_uniq_basic() {
+    run_tests(&[
+        TestCase {
+            name: "count ignores empty records",
+            program: "count",
+            arguments: &["-a"],
+            expected: "    2\tgoat\n",
+            stdin: Some(b"goat\n\n\ngoat\n"),
+            ..Default::default()
+        },
+        TestCase {
+            name: "count empty records",
+            program: "count",
+            arguments: &["-a", "-e"],
+            expected: "    2\tgoat\n    2\t\n",
+            stdin: Some(b"goat\n\n\ngoat\n"),
+            ..Default::default()
+        },
+        TestCase {
+            name: "uniq adjacent",
+            program: "uniq",
+            arguments: &["test-data/common1.txt", "test-data/common2.txt"],
+            expected: "Atlanta
+Boston
+Cincinnati
+Atlanta
+Boston
+cincinnati
+Detroit
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "uniq all",
+            program: "uniq",
+            arguments: &["-a", "test-data/common1.txt", "test-data/common2.txt"],
+            expected: "Atlanta
+Boston
+Cincinnati
+Detroit
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "count all, case-sensitive",
+            program: "count",
+            arguments: &["-a", "-S", "test-data/common1.txt", "test-data/common2.txt"],
+            expected: "    2	Atlanta
+    2	Boston
+    1	Cincinnati
+    1	cincinnati
+    1	Detroit
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "count by field",
+            program: "count",
+            arguments: &["-f", ", ", "-c", "1", "test-data/farm-animals.txt"],
+            expected: "    2	1	mountain goat	grass, moss, vegetation
+    1	12	sheep	grass, more grass
+    1	1,749	llamas	exclusively human flesh (for some reason)
+",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "count NDJSON",
+            program: "count",
+            arguments: &[
+                "-o",
+                "ndjson",
+                "-a",
+                "test-data/common1.txt",
+                "test-data/common2.txt",
+            ],
+            expected: r#"{"count":2,"r":"Atlanta"}
+{"count":2,"r":"Boston"}
+{"count":2,"r":"Cincinnati"}
+{"count":1,"r":"Detroit"}
+"#,
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
         },
     ]);
 }
@@ -467,6 +1245,7 @@ Cincinnati
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
         },
         TestCase {
             name: "common output field separator meaningless",
@@ -484,6 +1263,713 @@ Cincinnati
 ",
             sorted: false,
             expected_status: 0,
+            ..Default::default()
+        },
+    ]);
+}
+
+#[test]
+fn test_long_options() {
+    run_tests(&[
+        TestCase {
+            name: "records long options",
+            program: "records",
+            arguments: &[
+                "--no-enumerate",
+                "--limit",
+                "1",
+                "test-data/farm-animals.txt",
+            ],
+            expected: "1\tmountain goat\tgrass, moss, vegetation\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "fields long options with attached arguments",
+            program: "fields",
+            arguments: &[
+                "-n",
+                "--field=1",
+                "--field-delimiter=\\t",
+                "test-data/farm-animals.txt",
+            ],
+            expected: "mountain goat\nbilly goats\nsheep\nllamas\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "sort long option overrides",
+            program: "sort",
+            arguments: &["--reverse", "--compare=lexical", "test-data/common1.txt"],
+            expected: "Cincinnati\nBoston\nAtlanta\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "filter options end at --",
+            program: "filter",
+            arguments: &["-nm", "goat", "--", "test-data/farm-animals.txt"],
+            expected: "1\tmountain goat\tgrass, moss, vegetation\n4\tbilly goats\tgrass, moss, vegetation, tin cans\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+    ]);
+
+    let cases = [
+        ("records", &["-d", "1"][..]),
+        ("records", &["--depth=1"]),
+        ("sort", &["--no-such-option"]),
+        ("sort", &["--invert"]),
+        ("sort", &["--kind=lexical"]),
+    ]
+    .map(|(program, arguments)| TestCase {
+        name: "rejected option",
+        program,
+        arguments,
+        expected_status: 255,
+        expected_errors: true,
+        stdin: Some(b""),
+        ..Default::default()
+    });
+    run_tests(&cases);
+}
+
+#[test]
+fn test_output_formats() {
+    run_tests(&[
+        TestCase {
+            name: "records JSON array",
+            program: "records",
+            arguments: &["-o", "json", "-l", "2", "test-data/common1.txt"],
+            expected: r#"[
+{"n":0,"pathname":"test-data/common1.txt","r":"Atlanta"},
+{"n":1,"pathname":"test-data/common1.txt","r":"Boston"}
+]
+"#,
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "records JSON array, no records",
+            program: "records",
+            arguments: &["-o", "json", "-l", "0", "test-data/common1.txt"],
+            expected: "[]\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "filter JSON",
+            program: "filter",
+            arguments: &["-J", "-n", "-m", "llama", "test-data/farm-animals.txt"],
+            expected: r#"[
+{"n":null,"pathname":"test-data/farm-animals.txt","r":"1,749\tllamas\texclusively human flesh (for some reason)"}
+]
+"#,
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "common NDJSON",
+            program: "common",
+            arguments: &[
+                "-o",
+                "ndjson",
+                "-S",
+                "test-data/common1.txt",
+                "test-data/common2.txt",
+            ],
+            expected: r#"{"column":3,"r":"Atlanta"}
+{"column":3,"r":"Boston"}
+{"column":1,"r":"Cincinnati"}
+{"column":2,"r":"cincinnati"}
+{"column":2,"r":"Detroit"}
+"#,
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "files pretty JSON",
+            program: "files",
+            arguments: &["--output-format=pretty", "-S", "-m", "goat", "test-data"],
+            expected: r#"[
+{
+  "pathname": "test-data/goat"
+}
+]
+"#,
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "fields table",
+            program: "fields",
+            arguments: &["-n", "-o", "table", "test-data/columns.txt"],
+            expected: "0     1     2     3\nyeah  wow   hey   friends\nwhee  bonk  ouch  boing\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "fields named",
+            program: "fields",
+            arguments: &[
+                "-n",
+                "-H",
+                "pass",
+                "-c",
+                "friends",
+                "-c",
+                "/^w/",
+                "test-data/columns.txt",
+            ],
+            expected: "friends\twow\nboing\tbonk\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "fields named JSON",
+            program: "fields",
+            arguments: &[
+                "-n",
+                "-H",
+                "pass",
+                "-c",
+                "hey",
+                "-o",
+                "ndjson",
+                "test-data/columns.txt",
+            ],
+            expected: "{\"n\":null,\"pathname\":\"test-data/columns.txt\",\"fields\":{\"hey\":\"ouch\"}}\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+        TestCase {
+            name: "sort columns",
+            program: "sort",
+            arguments: &["-o", "columns", "test-data/common1.txt"],
+            expected: "Atlanta\nBoston\nCincinnati\n",
+            sorted: false,
+            expected_status: 0,
+            ..Default::default()
+        },
+    ]);
+
+    // `status -J` prints an array even for 1 pathname.
+    let output = Command::new(FUTILS)
+        .args(["status", "-J", "test-data/goat"])
+        .output()
+        .unwrap();
+    let output = String::from_utf8(output.stdout).unwrap();
+    assert!(output.starts_with("[\n{\""));
+    assert!(output.ends_with("}\n]\n"));
+    assert_eq!(1, output.matches("\"name\":").count());
+}
+
+#[test]
+fn test_config() {
+    let root = std::env::current_dir().unwrap().join("target/test-config");
+    _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("futils")).unwrap();
+    fs::write(
+        root.join("futils/config"),
+        "# Comment\noutput-record-delimiter = ;\n\n[fields]\noutput-field-delimiter = ,\n",
+    )
+    .unwrap();
+    let root = root.to_str().unwrap();
+
+    run_tests(&[
+        TestCase {
+            name: "config section",
+            program: "fields",
+            arguments: &["-n", "-c0", "-c2", "test-data/columns.txt"],
+            expected: "yeah,hey;whee,ouch;",
+            env: &[("XDG_CONFIG_HOME", root)],
+            ..Default::default()
+        },
+        TestCase {
+            name: "config global",
+            program: "records",
+            arguments: &["-n", "test-data/columns.txt"],
+            expected: "yeah\twow\they\tfriends;whee\tbonk\touch\tboing;",
+            env: &[("XDG_CONFIG_HOME", root)],
+            ..Default::default()
+        },
+        TestCase {
+            name: "config variable overrides file",
+            program: "fields",
+            arguments: &["-n", "-c0", "-c2", "test-data/columns.txt"],
+            expected: "yeah|hey;whee|ouch;",
+            env: &[
+                ("XDG_CONFIG_HOME", root),
+                ("FUTILS_OUTPUT_FIELD_DELIMITER", "|"),
+            ],
+            ..Default::default()
+        },
+        TestCase {
+            name: "config options override file",
+            program: "fields",
+            arguments: &[
+                "-n",
+                "-c0",
+                "-c2",
+                "-F",
+                " ",
+                "-R",
+                "\\n",
+                "test-data/columns.txt",
+            ],
+            expected: "yeah hey\nwhee ouch\n",
+            env: &[("XDG_CONFIG_HOME", root)],
+            ..Default::default()
+        },
+    ]);
+}
+
+#[test]
+fn test_trash_basic() {
+    let root = std::env::current_dir().unwrap().join("target/test-trash");
+    _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let data = root.join("data");
+    let goat = root.join("goat.txt");
+    fs::write(&goat, "baa\n").unwrap();
+    let config = root.join("config");
+    fs::create_dir_all(config.join("futils")).unwrap();
+    fs::write(
+        config.join("futils/config"),
+        "[trash]\noutput-record-delimiter = ;\n",
+    )
+    .unwrap();
+    let env = &[
+        ("XDG_DATA_HOME", data.to_str().unwrap()),
+        ("XDG_CONFIG_HOME", config.to_str().unwrap()),
+    ];
+    let pathname = goat.to_str().unwrap();
+    let trash_goat = [pathname];
+    let trash = |name, arguments, expected| TestCase {
+        name,
+        program: "trash",
+        arguments,
+        expected,
+        env,
+        cwd: Some(&root),
+        ..Default::default()
+    };
+
+    run_tests(&[trash("trash", &trash_goat, "")]);
+    assert!(!goat.exists());
+    assert!(data.join("Trash/files/goat.txt").exists());
+    let info = fs::read_to_string(data.join("Trash/info/goat.txt.trashinfo")).unwrap();
+    assert!(info.starts_with("[Trash Info]\n"));
+    assert!(info.contains(&format!("\nPath={pathname}\n")));
+
+    // The listing starts with the deletion time, so check only its end. The
+    // record delimiter comes from the `[trash]` section of the configuration.
+    let output = Command::new(FUTILS)
+        .args(["trash", "list", "-m", "goat"])
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+    assert!(output.stderr.is_empty());
+    assert!(output.stdout.ends_with(format!("\t{pathname};").as_bytes()));
+
+    run_tests(&[
+        trash("trash list no match", &["list", "-m", "sheep"], ""),
+        trash("trash restore", &["restore", "-m", "goat"], ""),
+        trash("trash list empty", &["list"], ""),
+    ]);
+    assert_eq!("baa\n", fs::read_to_string(&goat).unwrap());
+
+    // `restore` does not overwrite a file that has taken the trashed file’s
+    // place.
+    run_tests(&[trash("trash again", &trash_goat, "")]);
+    fs::write(&goat, "moo\n").unwrap();
+    run_tests(&[TestCase {
+        expected_status: 1,
+        expected_errors: true,
+        ..trash("trash restore no overwrite", &["restore", "-m", "goat"], "")
+    }]);
+    assert_eq!("moo\n", fs::read_to_string(&goat).unwrap());
+    fs::remove_file(&goat).unwrap();
+    run_tests(&[trash("trash restore all", &["restore"], "")]);
+    assert_eq!("baa\n", fs::read_to_string(&goat).unwrap());
+
+    run_tests(&[
+        trash("trash and empty", &trash_goat, ""),
+        trash("trash empty", &["empty"], ""),
+        trash("trash list emptied", &["list"], ""),
+    ]);
+    assert!(!goat.exists());
+    assert!(!data.join("Trash/files/goat.txt").exists());
+
+    // Like `rm`(1), `trash` refuses to trash `.` or `..`.
+    fs::create_dir_all(root.join("sheep")).unwrap();
+    run_tests(&[TestCase {
+        expected_status: 4,
+        expected_errors: true,
+        ..trash("trash dots", &[".", "..", "sheep/.", "sheep/../"], "")
+    }]);
+    assert!(root.join("sheep").exists());
+    assert!(!data.join("Trash/files/test-trash").exists());
+}
+
+#[test]
+fn test_non_utf8_pathnames() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let root = Path::new("target/test-non-utf8");
+    _ = fs::remove_dir_all(root);
+    fs::create_dir_all(root).unwrap();
+    // “café.txt” in Latin-1.
+    let cafe = root.join(OsStr::from_bytes(b"caf\xe9.txt"));
+    fs::write(&cafe, "goat\n").unwrap();
+
+    let case = |name, program, arguments, expected| TestCase::<OsStr, [u8]> {
+        name,
+        program,
+        arguments,
+        expected,
+        sorted: false,
+        expected_status: 0,
+        expected_errors: false,
+        env: &[],
+        cwd: None,
+        stdin: None,
+    };
+    let t = OsStr::new("-t");
+    let f = OsStr::new("f");
+    run_tests(&[
+        case(
+            "files non-UTF-8",
+            "files",
+            &[t, f, root.as_os_str()],
+            b"target/test-non-utf8/caf\xe9.txt\n",
+        ),
+        case(
+            "records non-UTF-8",
+            "records",
+            &[cafe.as_os_str()],
+            b"target/test-non-utf8/caf\xe9.txt\t    1\tgoat\n",
+        ),
+        case(
+            "files -x non-UTF-8",
+            "files",
+            &[
+                t,
+                f,
+                OsStr::new("-x"),
+                OsStr::new("grep -q goat"),
+                root.as_os_str(),
+            ],
+            b"target/test-non-utf8/caf\xe9.txt\n",
+        ),
+    ]);
+}
+
+#[test]
+fn test_csv() {
+    let animals = b"goat,\"grass, moss\"\nsheep,\"\"\"more\"\" grass\"\r\nllama,\"human\nflesh\"\n";
+    // Fields that contain the `-f` and `-F` delimiter (tab) come out as they
+    // went in.
+    let yaks = "yak,\"a\tb, c\",\"d\ne\"\nzebu,f\tg\n";
+    let root = Path::new("target/test-csv");
+    _ = fs::remove_dir_all(root);
+    fs::create_dir_all(root).unwrap();
+    let yaks_pathname = root.join("yaks.csv");
+    fs::write(&yaks_pathname, yaks).unwrap();
+    let yaks_pathname = yaks_pathname.to_str().unwrap();
+    let animals_pathname = root.join("animals.csv");
+    fs::write(&animals_pathname, animals).unwrap();
+    let animals_pathname = animals_pathname.to_str().unwrap();
+
+    run_tests(&[
+        TestCase {
+            name: "fields CSV",
+            program: "fields",
+            arguments: &["-C", "-n", "-c1"],
+            expected: "grass, moss\n\"more\" grass\nhuman\nflesh\n",
+            stdin: Some(animals),
+            ..Default::default()
+        },
+        TestCase {
+            name: "filter CSV",
+            program: "filter",
+            arguments: &["-C", "-n", "-m", "more"],
+            expected: "sheep\t\"more\" grass\n",
+            stdin: Some(animals),
+            ..Default::default()
+        },
+        TestCase {
+            name: "records CSV to CSV",
+            program: "records",
+            arguments: &["-C", "-n", "-o", "csv"],
+            expected: "goat,\"grass, moss\"\nsheep,\"\"\"more\"\" grass\"\nllama,\"human\nflesh\"\n",
+            stdin: Some(animals),
+            ..Default::default()
+        },
+        TestCase {
+            name: "fields CSV to CSV",
+            program: "fields",
+            arguments: &["-C", "-n", "-c1", "-o", "csv"],
+            expected: "\"grass, moss\"\n\"\"\"more\"\" grass\"\n\"human\nflesh\"\n",
+            stdin: Some(animals),
+            ..Default::default()
+        },
+        TestCase {
+            name: "common CSV to CSV",
+            program: "common",
+            arguments: &["-C", "-o", "csv", animals_pathname, animals_pathname],
+            expected: ",,goat,\"grass, moss\"\n,,sheep,\"\"\"more\"\" grass\"\n,,llama,\"human\nflesh\"\n",
+            ..Default::default()
+        },
+        TestCase {
+            name: "records CSV round trip",
+            program: "records",
+            arguments: &["-C", "-n", "-o", "csv"],
+            expected: yaks,
+            stdin: Some(yaks.as_bytes()),
+            ..Default::default()
+        },
+        TestCase {
+            name: "filter CSV round trip",
+            program: "filter",
+            arguments: &["-C", "-n", "-m", ".", "-o", "csv"],
+            expected: yaks,
+            stdin: Some(yaks.as_bytes()),
+            ..Default::default()
+        },
+        TestCase {
+            name: "common CSV round trip",
+            program: "common",
+            arguments: &["-C", "-o", "csv", yaks_pathname, yaks_pathname],
+            expected: ",,yak,\"a\tb, c\",\"d\ne\"\n,,zebu,f\tg\n",
+            ..Default::default()
+        },
+    ]);
+}
+
+#[test]
+fn test_parse_error_status() {
+    let cases = [
+        ("records", &["-j", "-n"][..]),
+        ("fields", &["-j", "-n"]),
+        ("filter", &["-j", "-n", "-m", "."]),
+    ]
+    .map(|(program, arguments)| TestCase {
+        name: program,
+        program,
+        arguments,
+        expected: "goat\n",
+        expected_status: 1,
+        expected_errors: true,
+        stdin: Some(b"{\"r\":\"goat\"} {\"r\":"),
+        ..Default::default()
+    });
+    run_tests(&cases);
+    run_tests(&[TestCase {
+        name: "apply",
+        program: "apply",
+        arguments: &["-j"],
+        expected: "goat\n",
+        expected_status: 1,
+        expected_errors: true,
+        stdin: Some(br#"[["echo","goat"]] ["#),
+        ..Default::default()
+    }]);
+}
+
+#[test]
+fn test_decompress() {
+    use bzip2::write::BzEncoder;
+    use flate2::{Compression, write::GzEncoder};
+    use std::{fs, io::Write, path::Path, process::Stdio};
+
+    let root = Path::new("target/test-decompress");
+    _ = fs::remove_dir_all(root);
+    fs::create_dir_all(root).unwrap();
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"goat\nsheep\nllama\n").unwrap();
+    let compressed = encoder.finish().unwrap();
+    // The name does not say it is compressed; `FileOpener` looks at the
+    // contents.
+    let animals = root.join("animals.log");
+    fs::write(&animals, &compressed).unwrap();
+    let animals = animals.to_str().unwrap();
+
+    let output = Command::new(FUTILS)
+        .args(["filter", "-n", "-m", "goat|llama", animals])
+        .output()
+        .unwrap();
+    assert!(output.stderr.is_empty());
+    assert_eq!(b"goat\nllama\n".as_slice(), output.stdout);
+
+    let output = Command::new(FUTILS)
+        .args(["filter", "-n", "-Z", "-m", "goat", animals])
+        .output()
+        .unwrap();
+    assert_ne!(b"goat\n".as_slice(), output.stdout);
+
+    let mut child = Command::new(FUTILS)
+        .args(["records", "-n"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&compressed).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(b"goat\nsheep\nllama\n".as_slice(), output.stdout);
+
+    // `common` and `join` decompress too, and bzip2 is detected by its whole
+    // magic.
+    let mut encoder = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    encoder.write_all(b"goat\nsheep\nllama\n").unwrap();
+    let bzipped = root.join("animals.bz");
+    fs::write(&bzipped, encoder.finish().unwrap()).unwrap();
+    let bzipped = bzipped.to_str().unwrap();
+    let output = Command::new(FUTILS)
+        .args(["common", animals, bzipped])
+        .output()
+        .unwrap();
+    assert!(output.stderr.is_empty());
+    assert_eq!(
+        b"\t\tgoat\n\t\tsheep\n\t\tllama\n".as_slice(),
+        output.stdout
+    );
+    let output = Command::new(FUTILS)
+        .args(["join", bzipped, animals])
+        .output()
+        .unwrap();
+    assert!(output.stderr.is_empty());
+    assert_eq!(b"goat\nsheep\nllama\n".as_slice(), output.stdout);
+
+    let plain = root.join("bzh.txt");
+    fs::write(&plain, "BZh9 is not bzip2\n").unwrap();
+    let output = Command::new(FUTILS)
+        .args(["records", "-n", plain.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(b"BZh9 is not bzip2\n".as_slice(), output.stdout);
+}
+
+#[test]
+fn test_mapped_and_streamed_records() {
+    use std::{fs, io::Write, path::Path, process::Stdio};
+
+    // Regular files are memory-mapped, and `stdin` is streamed; the records
+    // should be the same either way.
+    let root = Path::new("target/test-mapped");
+    _ = fs::remove_dir_all(root);
+    fs::create_dir_all(root).unwrap();
+    let contents = b"goat\n\nsheep\r\nllama";
+    let animals = root.join("animals.txt");
+    fs::write(&animals, contents).unwrap();
+    let animals = animals.to_str().unwrap();
+
+    for arguments in [
+        &["records", "-e", "-n"][..],
+        &["filter", "-e", "-n", "-m", "a"][..],
+        &["fields", "-n", "-c", "0"][..],
+    ] {
+        let mapped = Command::new(FUTILS)
+            .args(arguments)
+            .arg(animals)
+            .output()
+            .unwrap();
+        let mut child = Command::new(FUTILS)
+            .args(arguments)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(contents).unwrap();
+        let streamed = child.wait_with_output().unwrap();
+        assert_eq!(streamed.stdout, mapped.stdout);
+    }
+    assert_eq!(
+        b"goat\n\nsheep\nllama\n".as_slice(),
+        Command::new(FUTILS)
+            .args(["records", "-e", "-n", animals])
+            .output()
+            .unwrap()
+            .stdout
+    );
+}
+
+#[test]
+fn test_records_ranges_and_headers() {
+    let root = Path::new("target/test-headers");
+    _ = fs::remove_dir_all(root);
+    fs::create_dir_all(root).unwrap();
+    fs::write(root.join("goats.txt"), "Name\nbilly\nnanny\nkid\n").unwrap();
+    fs::write(root.join("sheep.txt"), "Name\newe\nram\n").unwrap();
+    // `head` exits long before it could read all of this, and `records` still
+    // runs it for the second file.
+    fs::write(
+        root.join("herd.txt"),
+        format!("Name\n{}", "goat\n".repeat(1 << 20)),
+    )
+    .unwrap();
+
+    let case = |name, arguments, expected| TestCase {
+        name,
+        program: "records",
+        arguments,
+        expected,
+        cwd: Some(root),
+        ..Default::default()
+    };
+    run_tests(&[
+        case(
+            "records ranges",
+            &["-n", "-L", "3..", "-L", "1", "goats.txt", "sheep.txt"],
+            "Name\nnanny\nkid\nName\nram\n",
+        ),
+        case(
+            "records pass header",
+            &["-n", "-H", "pass", "-L", "..:2", "goats.txt", "sheep.txt"],
+            "Name\nbilly\nkid\newe\n",
+        ),
+        case(
+            "records suppress header",
+            &["-n", "-H", "suppress", "goats.txt", "sheep.txt"],
+            "billy\nnanny\nkid\newe\nram\n",
+        ),
+        case(
+            "records repeat header with tail",
+            &["-n", "-H", "repeat", "-l", "-1", "goats.txt", "sheep.txt"],
+            "Name\nkid\nName\nram\n",
+        ),
+        case(
+            "records repeat header with command",
+            &["-n", "-H", "repeat", "-x", "sort", "goats.txt", "sheep.txt"],
+            "Name\nbilly\nkid\nnanny\nName\newe\nram\n",
+        ),
+        case(
+            "records command that exits early",
+            &["-n", "-x", "head -n 1", "goats.txt", "sheep.txt"],
+            "Name\nName\n",
+        ),
+        case(
+            "records command that exits early, large input",
+            &["-n", "-x", "head -n 1", "herd.txt", "herd.txt"],
+            "Name\nName\n",
+        ),
+        // The commands' output would not fit in the JSON array of headers.
+        TestCase {
+            expected_status: 255,
+            expected_errors: true,
+            ..case("records -J -x", &["-J", "-x", "sort", "goats.txt"], "")
         },
     ]);
 }